@@ -1,12 +1,29 @@
+use crate::outbox::QueuedMessage;
+use crate::shared::{Server, Shared};
+use chrono::format::{Item, StrftimeItems};
+use protocol::{normalize_reaction, MessageId, MessageType, DEFAULT_ROOM};
 use rodio::{Decoder, OutputStream, Sink};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::cell::{Ref, RefCell, RefMut};
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::PathBuf;
-use std::time::Instant;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use url::Url;
 
+// Number of older messages requested each time the view scrolls past the top
+const HISTORY_PAGE_SIZE: u32 = 50;
+// strftime format used for message times unless `TIMESTAMP_FORMAT` is set
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%H:%M";
+// Minimum time between typing events sent while composing; the server's indicators last
+// longer, so they stay up while the user keeps typing
+const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+// Reactions toggled with the number keys while a message is selected
+pub const QUICK_REACTIONS: [&str; 6] = ["👍", "❤️", "😂", "🎉", "👀", "👎"];
+// Time without key presses after which an online user is marked away
+const AUTO_AWAY_AFTER: Duration = Duration::from_secs(10 * 60);
+
 pub enum CurrentScreen {
     Main,
     SetUser,
@@ -18,12 +35,25 @@ pub enum CurrentScreen {
     ExitingLoggingIn,
     ServerSelection,
     AddServer,
+    DeletingServer, // asking to confirm deleting the selected server
+    Thread,         // a message and its replies, see `App::thread_parent`
+    Resuming,       // reconnected, waiting for the server to accept our session token
 }
 
 pub enum Command {
     SetName(String),
     ListUsers,
     DirectMessage(String, String), // recipient, message
+    JoinRoom(String),
+    LeaveRoom(Option<String>), // defaults to the current room
+    ListRooms,
+    EditLast(String), // replace the content of your latest message in the current room
+    DeleteLast,
+    React(Option<MessageId>, String), // message ID (the selected message by default), emoji
+    SetNotifications(NotificationMode),
+    Away(Option<String>), // optional reason
+    DoNotDisturb,
+    Back,
     Help,
     Unknown(String),
 }
@@ -31,13 +61,108 @@ pub enum Command {
 pub enum LoginField {
     Username,
     Password,
+    InviteCode, // only used when creating an account
+}
+
+pub enum LoginMode {
+    SignIn,
+    Register,
+}
+
+// Paging state for one room's history
+#[derive(Default)]
+pub struct RoomHistory {
+    pub cursor: Option<MessageId>, // oldest loaded message, used to page further back
+    pub has_more: bool,
+    pub request_pending: bool,
+}
+
+// Which incoming messages play the notification sound
+#[derive(Clone, Copy, PartialEq)]
+pub enum NotificationMode {
+    All,
+    Mentions, // only messages that mention the user, and direct messages to them
+    Off,
+}
+
+impl NotificationMode {
+    // Read the mode from `NOTIFICATIONS`, then the saved preference, defaulting to `All`
+    fn configured(preference: Option<&str>) -> NotificationMode {
+        std::env::var("NOTIFICATIONS")
+            .ok()
+            .and_then(|mode| NotificationMode::parse(&mode))
+            .or_else(|| preference.and_then(NotificationMode::parse))
+            .unwrap_or(NotificationMode::All)
+    }
+
+    // Name accepted by `parse`, as saved in the config
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotificationMode::All => "all",
+            NotificationMode::Mentions => "mentions",
+            NotificationMode::Off => "off",
+        }
+    }
+
+    pub fn parse(mode: &str) -> Option<NotificationMode> {
+        match mode.trim().to_ascii_lowercase().as_str() {
+            "all" => Some(NotificationMode::All),
+            "mentions" => Some(NotificationMode::Mentions),
+            "off" | "none" => Some(NotificationMode::Off),
+            _ => None,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            NotificationMode::All => "every message",
+            NotificationMode::Mentions => "mentions and direct messages",
+            NotificationMode::Off => "nothing",
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum MessageType {
-    ChatMessage { sender: String, content: String },
-    Command { name: String, args: Vec<String> },
-    SystemMessage(String),
+// The presence this client has asked the server to show for the user
+#[derive(Clone, Copy, PartialEq)]
+pub enum Status {
+    Online,
+    Away,
+    AutoAway, // set after `AUTO_AWAY_AFTER` without input, cleared by the next key press
+    DoNotDisturb,
+}
+
+impl Status {
+    // Marker shown in the chat header, or `None` when online
+    pub fn label(&self) -> Option<&'static str> {
+        match self {
+            Status::Online => None,
+            Status::Away | Status::AutoAway => Some("[away]"),
+            Status::DoNotDisturb => Some("[dnd]"),
+        }
+    }
+}
+
+// Delivery state of a message the server has not yet acknowledged. Once acknowledged it
+// becomes an ordinary `ChatMessage` in `App::messages` and is shown as sent.
+pub enum DeliveryState {
+    Queued, // written while offline, or sent on a connection that dropped; waiting to be sent
+    Pending,
+    Failed(String),
+}
+
+// Another user composing a message, shown until `expires_at`
+pub struct TypingUser {
+    pub room: String,
+    pub username: String,
+    pub expires_at: Instant,
+}
+
+pub struct PendingMessage {
+    pub nonce: String,
+    pub room: String,
+    pub content: String,
+    pub reply_to: Option<MessageId>,
+    pub state: DeliveryState,
 }
 
 pub struct App {
@@ -48,13 +173,41 @@ pub struct App {
     pub current_screen: CurrentScreen, // the current screen the user is looking at, and will later determine what is rendered.
     pub messages: Vec<MessageType>,
     pub scroll_offset: usize,
+    pub max_scroll_offset: usize, // lines above the view at the top of the loaded messages, set while rendering
+    pub rooms: Vec<String>,       // rooms this user has joined, in join order
+    pub current_room: String,     // room shown in the chat view and posted to
+    pub room_history: HashMap<String, RoomHistory>, // history paging state per room
+    pub pending_messages: Vec<PendingMessage>, // sent messages awaiting the server's ack
+    pub pending_join: Option<String>, // room requested with /join, switched to once joined
+    pub outgoing: VecDeque<MessageType>, // requests raised by the App, sent by the main loop
+    pub timestamp_format: String, // strftime format for message times, in local time
+    pub selected_message: Option<MessageId>, // message picked with the keyboard for reactions
+    pub thread_parent: Option<MessageId>, // thread shown on the thread screen
+    pub notification_mode: NotificationMode, // which messages play the notification sound
+    pub typing_users: Vec<TypingUser>, // other users composing messages
+    typing_sent_at: Option<Instant>, // when we last told the server we are typing
+    pub status: Status,
+    session_token: Option<String>, // from `LoginOk`, presented when reconnecting
+    pub online: bool,              // logged in on a live connection
+    pub reconnect_attempt: u32,    // automatic reconnect attempt in progress, from 1
+    pub reconnect_at: Option<Instant>, // when the next reconnect attempt starts
+    read_positions: HashMap<String, MessageId>, // newest message read in each room, as reported
+    unread_markers: HashMap<String, MessageId>, // read positions when we logged in
+    last_input: Instant,           // last key press, for auto-away
     pub compose_scroll_offset: usize,
     pub failed_login_attempts: u8,       // keep track of failed logins
     pub current_login_field: LoginField, // track current input on login
+    pub login_mode: LoginMode,           // signing in or creating an account
+    pub invite_code: Option<String>,     // optional invite code for registration
     pub is_typing: bool,                 // track if user is typing
-    pub servers: HashMap<String, Url>,   // storing servers
-    pub selected_server: Option<String>, // Track the selected server
-    pub selected_server_index: usize,
+    pub server: Option<Server>,          // server this tab is connected to
+    pub selected_server: Option<String>, // server picked on the server selection screen
+    pub editing_server: Option<String>,  // server being edited on the AddServer screen
+    pub server_error: Option<String>, // why the last server action failed, shown on the server screens
+    pub unread: usize,                // messages received while this tab was in the background
+    pub in_background: bool,          // another tab is shown
+    shared: Rc<RefCell<Shared>>,      // servers, config and outbox shared with the other tabs
+    #[allow(dead_code)]
     sound_sink: Sink,
     sound_path: PathBuf,
     last_notification_time: Option<Instant>,
@@ -62,17 +215,25 @@ pub struct App {
 
 impl App {
     pub fn new() -> App {
-        let mut servers = HashMap::new();
-        servers.insert(
-            "local".to_string(),
-            Url::parse("ws://0.0.0.0:8080").unwrap(),
-        );
-        servers.insert(
-            "default".to_string(),
-            Url::parse("ws://autorack.proxy.rlwy.net:55901").unwrap(),
-        );
-        let selected_server = Some("default".to_string());
-        let selected_server_index = 1;
+        let shared = Shared::load();
+        // Start on the server used last time
+        let selected_server = shared.initial_server();
+        App::with_shared(Rc::new(RefCell::new(shared)), selected_server)
+    }
+
+    // A fresh App for another tab, sharing the saved servers, config and outbox
+    pub fn new_tab(&self) -> App {
+        App::with_shared(self.shared.clone(), self.selected_server.clone())
+    }
+
+    fn with_shared(shared: Rc<RefCell<Shared>>, selected_server: Option<String>) -> App {
+        let (timestamp_format, notification_mode) = {
+            let ui = &shared.borrow().config.ui;
+            (
+                timestamp_format(ui.timestamp_format.as_deref()),
+                NotificationMode::configured(ui.notifications.as_deref()),
+            )
+        };
         // Initialize rodio components
         let (_stream, stream_handle) = OutputStream::try_default().unwrap();
         let sink = Sink::try_new(&stream_handle).unwrap();
@@ -90,13 +251,40 @@ impl App {
             current_screen: CurrentScreen::Main,
             messages: Vec::<MessageType>::new(),
             scroll_offset: 0,
+            max_scroll_offset: 0,
+            rooms: Vec::new(),
+            current_room: DEFAULT_ROOM.to_string(),
+            room_history: HashMap::new(),
+            pending_messages: Vec::new(),
+            pending_join: None,
+            outgoing: VecDeque::new(),
+            timestamp_format,
+            selected_message: None,
+            thread_parent: None,
+            notification_mode,
+            typing_users: Vec::new(),
+            typing_sent_at: None,
+            status: Status::Online,
+            session_token: None,
+            online: false,
+            reconnect_attempt: 0,
+            reconnect_at: None,
+            read_positions: HashMap::new(),
+            unread_markers: HashMap::new(),
+            last_input: Instant::now(),
             compose_scroll_offset: 0,
             failed_login_attempts: 0,
             current_login_field: LoginField::Username, // Default value
+            login_mode: LoginMode::SignIn,
+            invite_code: None,
             is_typing: false,
-            servers,
+            server: None,
             selected_server,
-            selected_server_index,
+            editing_server: None,
+            server_error: None,
+            unread: 0,
+            in_background: false,
+            shared,
             sound_sink: sink,
             sound_path: assets_path,
             last_notification_time: None,
@@ -126,54 +314,298 @@ impl App {
         });
     }
 
+    // Whether a new chat message should play the notification sound. The user's own
+    // messages never do.
+    fn should_notify(&self, message: &MessageType) -> bool {
+        let MessageType::ChatMessage {
+            sender,
+            recipient,
+            mentions,
+            ..
+        } = message
+        else {
+            return false;
+        };
+        let Some(username) = self.username.as_ref() else {
+            return false;
+        };
+        match self.notification_mode {
+            _ if sender == username => false,
+            _ if self.status == Status::DoNotDisturb => false,
+            NotificationMode::All => true,
+            NotificationMode::Mentions => {
+                mentions.contains(username) || recipient.as_ref() == Some(username)
+            }
+            NotificationMode::Off => false,
+        }
+    }
+
     // Handling incoming WebSocket messages from the server
     pub fn handle_websocket_message(&mut self, message: &str) {
-        if let Ok(message_type) = serde_json::from_str::<MessageType>(&message) {
+        if let Ok(message_type) = MessageType::from_json(message) {
             match message_type {
-                MessageType::ChatMessage { sender, content } => {
+                message @ MessageType::ChatMessage { id, .. } if self.has_message(id) => {
+                    // Our own message, already added when the server acknowledged it. The
+                    // server's copy also carries the mentions it found.
+                    if let Some(existing) = self.find_message_mut(id) {
+                        *existing = message;
+                    }
+                    return;
+                }
+                message @ MessageType::ChatMessage { .. } => {
+                    let notify = self.should_notify(&message);
+                    if let MessageType::ChatMessage { sender, room, .. } = &message {
+                        // Their message is here, so they are no longer typing it
+                        self.typing_users
+                            .retain(|typing| &typing.username != sender || &typing.room != room);
+                        if self.in_background && self.username.as_ref() != Some(sender) {
+                            self.unread += 1;
+                        }
+                    }
                     // Push the chat message into `self.messages`
-                    self.messages
-                        .push(MessageType::ChatMessage { sender, content });
+                    self.messages.push(message);
                     // Only play sound if there hasn't been a notification within the last 1 seconds
-                    if self
-                        .last_notification_time
-                        .map(|t| t.elapsed().as_secs() > 1)
-                        .unwrap_or(true)
+                    if notify
+                        && self
+                            .last_notification_time
+                            .map(|t| t.elapsed().as_secs() > 1)
+                            .unwrap_or(true)
                     {
                         self.play_notification_sound(); // Play sound on new chat message
                         self.last_notification_time = Some(Instant::now()); // Update time of last notification
                     }
                 }
-                MessageType::SystemMessage(system_message) => {
-                    if system_message.contains("Authentication successful") {
-                        // Push authentication success message
+                MessageType::Ack {
+                    nonce,
+                    id,
+                    timestamp,
+                } => {
+                    // The server stored our message: it becomes a regular, sent message
+                    self.shared_mut().outbox.remove(&nonce);
+                    // A message sent again after a reconnect may already have arrived with the
+                    // room's history
+                    if let Some(index) = self.pending_messages.iter().position(|p| p.nonce == nonce)
+                    {
+                        let pending = self.pending_messages.remove(index);
+                        if !self.has_message(id) {
+                            self.messages.push(MessageType::ChatMessage {
+                                id,
+                                timestamp,
+                                sender: self.username.clone().unwrap_or_default(),
+                                content: pending.content,
+                                room: pending.room,
+                                recipient: None,
+                                reply_to: pending.reply_to,
+                                mentions: Vec::new(),
+                                edited_at: None,
+                                deleted: false,
+                                reactions: Default::default(),
+                            });
+                        }
+                    }
+                }
+                MessageType::MessageEdited {
+                    id,
+                    content: new_content,
+                    mentions: new_mentions,
+                    edited_at: new_edited_at,
+                    ..
+                } => {
+                    // Update the message in place, wherever it is in the loaded history
+                    if let Some(MessageType::ChatMessage {
+                        content,
+                        mentions,
+                        edited_at,
+                        ..
+                    }) = self.find_message_mut(id)
+                    {
+                        *content = new_content;
+                        *mentions = new_mentions;
+                        *edited_at = Some(new_edited_at);
+                    }
+                    return;
+                }
+                MessageType::MessageDeleted { id, .. } => {
+                    if let Some(MessageType::ChatMessage {
+                        content, deleted, ..
+                    }) = self.find_message_mut(id)
+                    {
+                        content.clear();
+                        *deleted = true;
+                    }
+                    if self.selected_message == Some(id) {
+                        self.selected_message = None;
+                    }
+                    return;
+                }
+                MessageType::ReactionChanged {
+                    id,
+                    emoji,
+                    username,
+                    added,
+                    ..
+                } => {
+                    if let Some(MessageType::ChatMessage { reactions, .. }) =
+                        self.find_message_mut(id)
+                    {
+                        let users = reactions.entry(emoji.clone()).or_default();
+                        users.retain(|user| user != &username);
+                        if added {
+                            users.push(username);
+                        }
+                        if users.is_empty() {
+                            reactions.remove(&emoji);
+                        }
+                    }
+                    return;
+                }
+                MessageType::UserTyping {
+                    room,
+                    username,
+                    expires_in_ms,
+                } => {
+                    let expires_at = Instant::now() + Duration::from_millis(expires_in_ms);
+                    match self
+                        .typing_users
+                        .iter_mut()
+                        .find(|typing| typing.room == room && typing.username == username)
+                    {
+                        Some(typing) => typing.expires_at = expires_at,
+                        None => self.typing_users.push(TypingUser {
+                            room,
+                            username,
+                            expires_at,
+                        }),
+                    }
+                    return;
+                }
+                MessageType::SendFailed { nonce, reason } => {
+                    // Rejected messages would only be rejected again
+                    self.shared_mut().outbox.remove(&nonce);
+                    if let Some(pending) =
+                        self.pending_messages.iter_mut().find(|p| p.nonce == nonce)
+                    {
+                        pending.state = DeliveryState::Failed(reason);
+                    }
+                }
+                MessageType::LoginOk { username, token } => {
+                    // Push authentication success message
+                    let notice = match self.current_screen {
+                        CurrentScreen::Resuming => "Reconnected.",
+                        _ => "You are authenticated!",
+                    };
+                    self.messages
+                        .push(MessageType::SystemMessage(notice.to_string()));
+                    self.current_screen = self.chat_screen();
+                    self.failed_login_attempts = 0; // Reset failed attempts on success
+                    self.set_username(username);
+                    self.session_token = Some(token);
+                    self.online = true;
+                    // Requests sent on a dropped connection are never answered
+                    for history in self.room_history.values_mut() {
+                        history.request_pending = false;
+                    }
+                    self.flush_outbox();
+                }
+                MessageType::ResumeFailed => {
+                    // Start over with a password login and a fresh copy of history
+                    self.reset_history();
+                    self.start_login();
+                    self.login_mode = LoginMode::SignIn;
+                    self.messages.push(MessageType::SystemMessage(
+                        "Your session has expired. Please log in again.".to_string(),
+                    ));
+                    self.current_screen = CurrentScreen::LoggingIn;
+                }
+                MessageType::RegisterOk => {
+                    self.messages
+                        .push(MessageType::SystemMessage("Account created.".to_string()));
+                }
+                MessageType::RegisterFailed { reason } => {
+                    self.messages.push(MessageType::SystemMessage(format!(
+                        "Registration failed: {}",
+                        reason
+                    )));
+                    self.current_screen = CurrentScreen::LoggingIn;
+                }
+                MessageType::LoginFailed { remaining_attempts } => {
+                    self.failed_login_attempts += 1; // Increment failed attempts
+                                                     // Push authentication failure message
+                    self.messages.push(MessageType::SystemMessage(format!(
+                        "Authentication failed. {} attempts remaining.",
+                        remaining_attempts
+                    )));
+                    if remaining_attempts == 0 {
+                        self.current_screen = CurrentScreen::Disconnected; // Server closes after max attempts
                         self.messages.push(MessageType::SystemMessage(
-                            "You are authenticated!".to_string(),
+                            "Max login attempts reached. Connection closed.".to_string(),
                         ));
-                        self.current_screen = CurrentScreen::Main;
-                        self.failed_login_attempts = 0; // Reset failed attempts on success
-                        self.username = self.staging_username.clone();
-                    } else if system_message.contains("Authentication failed") {
-                        self.failed_login_attempts += 1; // Increment failed attempts
-                        let remaining_attempts = 5 - self.failed_login_attempts;
-                        // Push authentication failure message
-                        self.messages.push(MessageType::SystemMessage(format!(
-                            "Authentication failed. {} attempts remaining.",
-                            remaining_attempts
-                        )));
-                        if self.failed_login_attempts >= 5 {
-                            self.current_screen = CurrentScreen::Disconnected; // Disconnect after max attempts
-                            self.messages.push(MessageType::SystemMessage(
-                                "Max login attempts reached. Connection closed.".to_string(),
-                            ));
-                        } else {
-                            self.current_screen = CurrentScreen::LoggingIn; // Retry login
-                        }
                     } else {
-                        // Push any other system message received
-                        self.messages
-                            .push(MessageType::SystemMessage(system_message));
+                        self.current_screen = CurrentScreen::LoggingIn; // Retry login
+                    }
+                }
+                MessageType::History {
+                    room,
+                    messages,
+                    oldest_id,
+                    has_more,
+                } => {
+                    self.add_history_page(room, messages, oldest_id, has_more);
+                    return;
+                }
+                MessageType::ReadPosition { room, last_read } => {
+                    // Messages after this one arrived while we were away
+                    self.read_positions.insert(room.clone(), last_read);
+                    self.unread_markers.insert(room, last_read);
+                }
+                MessageType::RoomJoined { room } => {
+                    // Rooms are sent again after a reconnect; only new ones are announced
+                    if self.rooms.contains(&room) {
+                        return;
                     }
+                    self.rooms.push(room.clone());
+                    // The first room after logging in becomes the active one, and a room
+                    // joined with /join is switched to straight away
+                    if self.rooms.len() == 1 || self.pending_join.as_ref() == Some(&room) {
+                        self.pending_join = None;
+                        self.switch_room(&room);
+                    }
+                    self.messages
+                        .push(MessageType::SystemMessage(format!("Joined {}", room)));
+                }
+                MessageType::RoomLeft { room } => {
+                    self.rooms.retain(|joined| joined != &room);
+                    self.room_history.remove(&room);
+                    self.messages.retain(|message| {
+                        !matches!(message, MessageType::ChatMessage { room: r, .. } if r == &room)
+                    });
+                    if self.thread_room().as_ref() == Some(&room) {
+                        self.close_thread();
+                    }
+                    if self.current_room == room {
+                        let next = self
+                            .rooms
+                            .first()
+                            .cloned()
+                            .unwrap_or_else(|| DEFAULT_ROOM.to_string());
+                        self.switch_room(&next);
+                    }
+                    self.messages
+                        .push(MessageType::SystemMessage(format!("Left {}", room)));
+                }
+                MessageType::RoomList { rooms } => {
+                    let summary = rooms
+                        .iter()
+                        .map(|room| format!("{} ({})", room.name, room.members))
+                        .collect::<Vec<String>>()
+                        .join(", ");
+                    self.messages
+                        .push(MessageType::SystemMessage(format!("Rooms: {}", summary)));
+                }
+                MessageType::SystemMessage(system_message) => {
+                    // Push any other system message received
+                    self.messages
+                        .push(MessageType::SystemMessage(system_message));
                 }
                 _ => {}
             }
@@ -187,7 +619,654 @@ impl App {
     }
     // Methods for scrolling up and down in main chat
     pub fn scroll_up(&mut self) {
-        self.scroll_offset = self.scroll_offset.saturating_add(1);
+        if self.thread_parent.is_some() {
+            // Threads are fully loaded with the history that contains them
+            self.scroll_offset = (self.scroll_offset + 1).min(self.max_scroll_offset);
+        } else if self.scroll_offset >= self.max_scroll_offset {
+            // Reached the top of the loaded messages, backfill from the server
+            self.request_older_history();
+        } else {
+            self.scroll_offset = self.scroll_offset.saturating_add(1);
+        }
+    }
+
+    // Queue a request for the page of the current room's history before its oldest
+    // loaded message
+    pub fn request_older_history(&mut self) {
+        let history = self
+            .room_history
+            .entry(self.current_room.clone())
+            .or_default();
+        if history.has_more && !history.request_pending {
+            self.outgoing.push_back(MessageType::FetchHistory {
+                room: self.current_room.clone(),
+                before_id: history.cursor,
+                limit: HISTORY_PAGE_SIZE,
+            });
+            history.request_pending = true;
+        }
+    }
+
+    // Add a page of a room's history: the older page we asked for, or the newest page sent
+    // after logging in or resuming. Messages already loaded are replaced by the server's copy,
+    // which carries any edits, deletions and reactions missed while offline. A newest page
+    // that does not reach back to the loaded messages replaces them, leaving the gap to be
+    // paged back through as usual.
+    fn add_history_page(
+        &mut self,
+        room: String,
+        messages: Vec<MessageType>,
+        oldest_id: Option<MessageId>,
+        has_more: bool,
+    ) {
+        let newest_loaded = self
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                MessageType::ChatMessage { id, room: r, .. } if *r == room => Some(*id),
+                _ => None,
+            })
+            .max();
+        let history = self.room_history.entry(room.clone()).or_default();
+        let older = std::mem::take(&mut history.request_pending);
+        let gap = matches!((newest_loaded, oldest_id), (Some(newest), Some(oldest))
+            if has_more && oldest > newest);
+        match history.cursor {
+            // A newest page overlapping what is loaded keeps the paging state of the
+            // messages loaded further back
+            Some(cursor) if !older && !gap && oldest_id.is_none_or(|oldest| cursor <= oldest) => {}
+            _ => {
+                history.cursor = oldest_id.or(history.cursor);
+                history.has_more = has_more;
+            }
+        }
+        if gap {
+            self.messages.retain(
+                |message| !matches!(message, MessageType::ChatMessage { room: r, .. } if *r == room),
+            );
+            if self.thread_parent.is_some() && self.thread_room().is_none() {
+                self.close_thread();
+            }
+        }
+
+        // Each message goes before the first later one in its room, so older pages go above
+        // the loaded messages. The scroll offset counts lines from the bottom, so the current
+        // view stays where it is.
+        for message in messages {
+            let MessageType::ChatMessage { id, .. } = message else {
+                continue;
+            };
+            if let Some(existing) = self.find_message_mut(id) {
+                *existing = message;
+                continue;
+            }
+            let position = self.messages.iter().position(|loaded| {
+                matches!(loaded, MessageType::ChatMessage { id: later, room: r, .. }
+                    if *r == room && *later > id)
+            });
+            match position {
+                Some(index) => self.messages.insert(index, message),
+                None => self.messages.push(message),
+            }
+        }
+        if self
+            .selected_message
+            .is_some_and(|selected| !self.has_message(selected))
+        {
+            self.selected_message = None;
+        }
+    }
+
+    // Forget the messages, rooms and history cursors of the previous connection
+    pub fn reset_history(&mut self) {
+        self.messages.clear();
+        self.scroll_offset = 0;
+        self.rooms.clear();
+        self.current_room = DEFAULT_ROOM.to_string();
+        self.room_history.clear();
+        self.pending_messages.clear();
+        self.pending_join = None;
+        self.outgoing.clear();
+        self.selected_message = None;
+        self.thread_parent = None;
+        self.typing_users.clear();
+        self.typing_sent_at = None;
+        self.status = Status::Online;
+        self.last_input = Instant::now();
+        self.read_positions.clear();
+        self.unread_markers.clear();
+        self.session_token = None;
+        self.online = false;
+    }
+
+    // Send a new message to the current room, or a reply in the open thread. It is shown as
+    // pending until the server acknowledges it, and kept in the outbox until then so it is
+    // sent again if the connection drops first. While offline it only waits in the outbox.
+    pub fn send_message(&mut self, content: String) {
+        let nonce = uuid::Uuid::new_v4().to_string();
+        let room = self
+            .thread_room()
+            .unwrap_or_else(|| self.current_room.clone());
+        let reply_to = self.thread_parent;
+        self.shared.borrow_mut().outbox.push(QueuedMessage {
+            server: self.server_name().unwrap_or_default().to_string(),
+            username: self.username.clone().unwrap_or_default(),
+            nonce: nonce.clone(),
+            room: room.clone(),
+            content: content.clone(),
+            reply_to,
+        });
+        let state = if self.online {
+            self.outgoing.push_back(MessageType::SendMessage {
+                room: room.clone(),
+                content: content.clone(),
+                nonce: nonce.clone(),
+                reply_to,
+            });
+            DeliveryState::Pending
+        } else {
+            DeliveryState::Queued
+        };
+        self.pending_messages.push(PendingMessage {
+            nonce,
+            room,
+            content,
+            reply_to,
+            state,
+        });
+    }
+
+    // Send, in order, everything in the outbox from this user for the current server: messages
+    // written offline, those the last connection dropped before they were acknowledged and
+    // any left over from an earlier run
+    fn flush_outbox(&mut self) {
+        let server = self.server_name().unwrap_or_default().to_string();
+        let username = self.username.clone().unwrap_or_default();
+        let queued_messages = self.shared().outbox.queued(&server, &username);
+        for queued in queued_messages {
+            match self
+                .pending_messages
+                .iter_mut()
+                .find(|pending| pending.nonce == queued.nonce)
+            {
+                Some(pending) => pending.state = DeliveryState::Pending,
+                None => self.pending_messages.push(PendingMessage {
+                    nonce: queued.nonce.clone(),
+                    room: queued.room.clone(),
+                    content: queued.content.clone(),
+                    reply_to: queued.reply_to,
+                    state: DeliveryState::Pending,
+                }),
+            }
+            self.outgoing.push_back(MessageType::SendMessage {
+                room: queued.room,
+                content: queued.content,
+                nonce: queued.nonce,
+                reply_to: queued.reply_to,
+            });
+        }
+    }
+
+    // Queue a command for the server; requests made while offline go out after reconnecting
+    pub fn send_command(&mut self, name: &str, args: Vec<String>) {
+        self.outgoing.push_back(MessageType::Command {
+            name: name.to_string(),
+            args,
+        });
+    }
+
+    // Called as the compose box changes: queue a typing event for the room being written
+    // to, at most once per `TYPING_THROTTLE`. Commands do not count as typing.
+    pub fn note_composing(&mut self) {
+        if !self.online
+            || self.message_input.trim().is_empty()
+            || self.message_input.starts_with('/')
+        {
+            return;
+        }
+        if self
+            .typing_sent_at
+            .is_some_and(|sent| sent.elapsed() < TYPING_THROTTLE)
+        {
+            return;
+        }
+        let room = self
+            .thread_room()
+            .unwrap_or_else(|| self.current_room.clone());
+        self.outgoing.push_back(MessageType::Typing { room });
+        self.typing_sent_at = Some(Instant::now());
+    }
+
+    // The composed message was sent or abandoned; typing again starts a new indicator
+    pub fn stop_composing(&mut self) {
+        self.typing_sent_at = None;
+    }
+
+    // Usernames of the other users currently typing in the room on screen
+    pub fn typing_in_view(&self) -> Vec<&str> {
+        let room = self
+            .thread_room()
+            .unwrap_or_else(|| self.current_room.clone());
+        let now = Instant::now();
+        self.typing_users
+            .iter()
+            .filter(|typing| typing.room == room && typing.expires_at > now)
+            .map(|typing| typing.username.as_str())
+            .collect()
+    }
+
+    // Change the user's presence and queue the command that tells the server
+    pub fn set_status(&mut self, status: Status, reason: Option<String>) {
+        let name = match status {
+            Status::Online => "back",
+            Status::Away | Status::AutoAway => "away",
+            Status::DoNotDisturb => "dnd",
+        };
+        self.status = status;
+        self.send_command(name, reason.into_iter().collect());
+    }
+
+    // Called on every key press once logged in: returns from auto-away
+    pub fn note_activity(&mut self) {
+        self.last_input = Instant::now();
+        if self.status == Status::AutoAway {
+            self.set_status(Status::Online, None);
+        }
+    }
+
+    // Mark an online user away once they have been idle for `AUTO_AWAY_AFTER`, returning
+    // whether the status changed
+    pub fn check_idle(&mut self) -> bool {
+        if self.username.is_none()
+            || self.status != Status::Online
+            || self.last_input.elapsed() < AUTO_AWAY_AFTER
+        {
+            return false;
+        }
+        self.set_status(Status::AutoAway, Some("idle".to_string()));
+        true
+    }
+
+    // While the room view is scrolled to the bottom, queue a read receipt for the newest
+    // message shown from each room: the active room and any direct messages
+    pub fn mark_read(&mut self) {
+        if self.scroll_offset != 0
+            || !matches!(
+                self.current_screen,
+                CurrentScreen::Main | CurrentScreen::ComposingMessage
+            )
+        {
+            return;
+        }
+        let mut newest: HashMap<&String, MessageId> = HashMap::new();
+        for message in &self.messages {
+            if let MessageType::ChatMessage {
+                id,
+                room,
+                recipient,
+                ..
+            } = message
+            {
+                if room == &self.current_room || recipient.is_some() {
+                    let newest = newest.entry(room).or_default();
+                    *newest = (*newest).max(*id);
+                }
+            }
+        }
+        for (room, id) in newest {
+            if self
+                .read_positions
+                .get(room)
+                .is_some_and(|read| *read >= id)
+            {
+                continue;
+            }
+            self.read_positions.insert(room.clone(), id);
+            self.outgoing.push_back(MessageType::MarkRead {
+                room: room.clone(),
+                id,
+            });
+        }
+    }
+
+    // The first message from someone else that arrived after the read position we had
+    // when logging in, where the "new messages" divider goes
+    pub fn first_unread(&self) -> Option<MessageId> {
+        self.visible_messages()
+            .into_iter()
+            .find_map(|message| match message {
+                MessageType::ChatMessage {
+                    id, sender, room, ..
+                } if self.username.as_ref() != Some(&sender)
+                    && self
+                        .unread_markers
+                        .get(&room)
+                        .is_some_and(|read| id > *read) =>
+                {
+                    Some(id)
+                }
+                _ => None,
+            })
+    }
+
+    // Drop expired typing indicators, returning whether any were removed
+    pub fn expire_typing(&mut self) -> bool {
+        let now = Instant::now();
+        let before = self.typing_users.len();
+        self.typing_users.retain(|typing| typing.expires_at > now);
+        self.typing_users.len() != before
+    }
+
+    // URL of the server picked on the server selection screen
+    pub fn selected_server_url(&self) -> Option<Url> {
+        let index = self.selected_server_index()?;
+        Some(self.shared().servers[index].url.clone())
+    }
+
+    pub fn server_name(&self) -> Option<&str> {
+        self.server.as_ref().map(|server| server.name.as_str())
+    }
+
+    // URL of the server this tab is connected to, as currently saved: it may have been edited
+    // since the tab connected. A server deleted since keeps the URL the tab connected with.
+    pub fn server_url(&self) -> Option<Url> {
+        let server = self.server.as_ref()?;
+        let shared = self.shared();
+        let url = match shared.position(&server.name) {
+            Some(index) => shared.servers[index].url.clone(),
+            None => server.url.clone(),
+        };
+        Some(url)
+    }
+
+    // Whether a dropped connection can be picked up again without the password
+    pub fn can_resume(&self) -> bool {
+        self.session_token.is_some()
+    }
+
+    // The request that resumes our session on a new connection, if we have a token. The
+    // server replays what arrived after the newest message we already have.
+    pub fn resume_request(&self) -> Option<MessageType> {
+        let token = self.session_token.clone()?;
+        let last_seen_id = self
+            .messages
+            .iter()
+            .filter_map(|message| match message {
+                MessageType::ChatMessage { id, .. } => Some(*id),
+                _ => None,
+            })
+            .max();
+        Some(MessageType::Resume {
+            token,
+            last_seen_id,
+        })
+    }
+
+    // Show every message still waiting for an ack as queued when the connection drops. They
+    // are still in the outbox and go out again once the session is back.
+    pub fn requeue_pending_messages(&mut self) {
+        for pending in self.pending_messages.iter_mut() {
+            if let DeliveryState::Pending = pending.state {
+                pending.state = DeliveryState::Queued;
+            }
+        }
+    }
+
+    fn find_message_mut(&mut self, id: MessageId) -> Option<&mut MessageType> {
+        self.messages.iter_mut().find(
+            |message| matches!(message, MessageType::ChatMessage { id: existing, .. } if *existing == id),
+        )
+    }
+
+    // ID of the user's most recent message in the current room that can still be changed
+    fn last_own_message_id(&self) -> Option<MessageId> {
+        self.messages
+            .iter()
+            .rev()
+            .find_map(|message| match message {
+                MessageType::ChatMessage {
+                    id,
+                    sender,
+                    room,
+                    deleted: false,
+                    ..
+                } if room == &self.current_room && Some(sender) == self.username.as_ref() => {
+                    Some(*id)
+                }
+                _ => None,
+            })
+    }
+
+    // Queue an edit of the user's latest message in the current room
+    pub fn edit_last_message(&mut self, content: String) {
+        match self.last_own_message_id() {
+            Some(id) => self
+                .outgoing
+                .push_back(MessageType::EditMessage { id, content }),
+            None => self.messages.push(MessageType::SystemMessage(
+                "You have no message to edit in this room.".to_string(),
+            )),
+        }
+    }
+
+    // Queue deletion of the user's latest message in the current room
+    pub fn delete_last_message(&mut self) {
+        match self.last_own_message_id() {
+            Some(id) => self.outgoing.push_back(MessageType::DeleteMessage { id }),
+            None => self.messages.push(MessageType::SystemMessage(
+                "You have no message to delete in this room.".to_string(),
+            )),
+        }
+    }
+
+    // Queue adding the user's reaction to a message, or removing it if they already reacted
+    // with that emoji
+    pub fn toggle_reaction(&mut self, id: Option<MessageId>, emoji: &str) {
+        let Some(id) = id.or(self.selected_message) else {
+            self.messages.push(MessageType::SystemMessage(
+                "Select a message first, or use /react <id> <emoji>.".to_string(),
+            ));
+            return;
+        };
+        let Some(emoji) = normalize_reaction(emoji) else {
+            self.messages.push(MessageType::SystemMessage(format!(
+                "'{}' is not a reaction; use an emoji or :shortcode:.",
+                emoji
+            )));
+            return;
+        };
+        let username = self.username.clone().unwrap_or_default();
+        let reacted = self.messages.iter().any(|message| {
+            matches!(message, MessageType::ChatMessage { id: existing, reactions, .. }
+                if *existing == id
+                    && reactions.get(&emoji).is_some_and(|users| users.contains(&username)))
+        });
+        self.outgoing.push_back(if reacted {
+            MessageType::RemoveReaction { id, emoji }
+        } else {
+            MessageType::AddReaction { id, emoji }
+        });
+    }
+
+    // IDs of the messages in the current view that can be selected, oldest first
+    fn selectable_message_ids(&self) -> Vec<MessageId> {
+        // Deleted messages can only be selected to open the thread they started
+        let reply_counts = self.reply_counts();
+        self.visible_messages()
+            .iter()
+            .filter_map(|message| match message {
+                MessageType::ChatMessage { id, deleted, .. }
+                    if !deleted || reply_counts.contains_key(id) =>
+                {
+                    Some(*id)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Move the selection one message up (older) or down (newer), starting from the newest
+    // message. Moving down past the newest message clears the selection.
+    pub fn move_selection(&mut self, older: bool) {
+        let ids = self.selectable_message_ids();
+        let position = self
+            .selected_message
+            .and_then(|selected| ids.iter().position(|id| *id == selected));
+        self.selected_message = match (position, older) {
+            (None, _) => ids.last().copied(),
+            (Some(index), true) => ids.get(index.saturating_sub(1)).copied(),
+            (Some(index), false) => ids.get(index + 1).copied(),
+        };
+    }
+
+    fn has_message(&self, id: MessageId) -> bool {
+        self.messages
+            .iter()
+            .any(|message| matches!(message, MessageType::ChatMessage { id: existing, .. } if *existing == id))
+    }
+
+    // Unacknowledged messages for the current view, shown below its messages
+    pub fn room_pending_messages(&self) -> Vec<&PendingMessage> {
+        let room = self
+            .thread_room()
+            .unwrap_or_else(|| self.current_room.clone());
+        self.pending_messages
+            .iter()
+            .filter(|pending| pending.room == room && pending.reply_to == self.thread_parent)
+            .collect()
+    }
+
+    // Messages shown in the chat view: the current room's messages outside threads, plus
+    // direct and system messages which are shown whichever room is active
+    pub fn room_messages(&self) -> Vec<MessageType> {
+        self.messages
+            .iter()
+            .filter(|message| match message {
+                MessageType::ChatMessage {
+                    room,
+                    recipient: None,
+                    reply_to,
+                    ..
+                } => room == &self.current_room && reply_to.is_none(),
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    // The open thread: its first message followed by the replies
+    pub fn thread_messages(&self) -> Vec<MessageType> {
+        let Some(parent) = self.thread_parent else {
+            return Vec::new();
+        };
+        self.messages
+            .iter()
+            .filter(|message| {
+                matches!(message, MessageType::ChatMessage { id, reply_to, .. }
+                    if *id == parent || *reply_to == Some(parent))
+            })
+            .cloned()
+            .collect()
+    }
+
+    // Messages on screen: the open thread, or the chat view
+    pub fn visible_messages(&self) -> Vec<MessageType> {
+        if self.thread_parent.is_some() {
+            self.thread_messages()
+        } else {
+            self.room_messages()
+        }
+    }
+
+    // Number of loaded replies to each message that has any. History is loaded newest
+    // first, so every reply to a loaded message is loaded too.
+    pub fn reply_counts(&self) -> HashMap<MessageId, usize> {
+        let mut counts = HashMap::new();
+        for message in &self.messages {
+            if let MessageType::ChatMessage {
+                reply_to: Some(parent),
+                ..
+            } = message
+            {
+                *counts.entry(*parent).or_insert(0) += 1;
+            }
+        }
+        counts
+    }
+
+    // Room of the open thread
+    pub fn thread_room(&self) -> Option<String> {
+        let parent = self.thread_parent?;
+        self.messages.iter().find_map(|message| match message {
+            MessageType::ChatMessage { id, room, .. } if *id == parent => Some(room.clone()),
+            _ => None,
+        })
+    }
+
+    // Open the thread of the selected message. Replies open the thread they are part of;
+    // direct messages cannot have threads.
+    pub fn open_selected_thread(&mut self) {
+        let Some(selected) = self.selected_message else {
+            return;
+        };
+        let parent = self.messages.iter().find_map(|message| match message {
+            MessageType::ChatMessage {
+                id,
+                recipient: None,
+                reply_to,
+                ..
+            } if *id == selected => Some(reply_to.unwrap_or(*id)),
+            _ => None,
+        });
+        match parent {
+            Some(parent) => {
+                self.thread_parent = Some(parent);
+                self.selected_message = None;
+                self.scroll_offset = 0;
+                self.current_screen = CurrentScreen::Thread;
+            }
+            None => self.messages.push(MessageType::SystemMessage(
+                "Direct messages cannot have threads.".to_string(),
+            )),
+        }
+    }
+
+    pub fn close_thread(&mut self) {
+        self.thread_parent = None;
+        self.selected_message = None;
+        self.scroll_offset = 0;
+        if let CurrentScreen::Thread = self.current_screen {
+            self.current_screen = CurrentScreen::Main;
+        }
+    }
+
+    // Chat screen to go back to from composing or the help menu: the open thread, if any
+    pub fn chat_screen(&self) -> CurrentScreen {
+        if self.thread_parent.is_some() {
+            CurrentScreen::Thread
+        } else {
+            CurrentScreen::Main
+        }
+    }
+
+    pub fn switch_room(&mut self, room: &str) {
+        self.current_room = room.to_string();
+        self.scroll_offset = 0;
+        self.selected_message = None;
+    }
+
+    // Cycle the chat view through the joined rooms
+    pub fn next_room(&mut self) {
+        if self.rooms.is_empty() {
+            return;
+        }
+        let index = self
+            .rooms
+            .iter()
+            .position(|room| room == &self.current_room)
+            .map_or(0, |index| (index + 1) % self.rooms.len());
+        let room = self.rooms[index].clone();
+        self.switch_room(&room);
     }
 
     pub fn scroll_down(&mut self) {
@@ -203,21 +1282,198 @@ impl App {
         self.compose_scroll_offset = self.scroll_offset.saturating_sub(1);
     }
 
-    // Method for setting username
+    // Clear the login form and start again from the username field
+    pub fn reset_login_fields(&mut self) {
+        self.username = None;
+        self.password = None;
+        self.invite_code = None;
+        self.message_input.clear();
+        self.current_login_field = LoginField::Username;
+        self.is_typing = false;
+    }
+
+    // Clear the login form for a new sign-in, filling in the username last used on this
+    // server so only the password is left to type
+    pub fn start_login(&mut self) {
+        self.reset_login_fields();
+        let remembered = self
+            .server_name()
+            .and_then(|server| self.shared().config.usernames.get(server).cloned());
+        if let Some(username) = remembered {
+            self.username = Some(username);
+            self.current_login_field = LoginField::Password;
+        }
+    }
+
+    // Method for setting username, remembered for the next login to this server
     pub fn set_username(&mut self, name: String) {
+        if let Some(server) = self.server_name().map(str::to_string) {
+            let mut shared = self.shared_mut();
+            if shared.config.usernames.get(&server) != Some(&name) {
+                shared.config.usernames.insert(server, name.clone());
+                shared.save_config();
+            }
+        }
         self.username = Some(name);
     }
+
+    pub fn shared(&self) -> Ref<'_, Shared> {
+        self.shared.borrow()
+    }
+
+    fn shared_mut(&self) -> RefMut<'_, Shared> {
+        self.shared.borrow_mut()
+    }
+
+    // Position of the selected server in the list
+    pub fn selected_server_index(&self) -> Option<usize> {
+        self.shared().position(self.selected_server.as_ref()?)
+    }
+
+    // Move the selection `step` places through the server list, wrapping around at the ends
+    // when `wrap` is set. Nothing selected starts from the first server.
+    pub fn select_server(&mut self, step: isize, wrap: bool) {
+        let len = self.shared().servers.len() as isize;
+        let index = match self.selected_server_index() {
+            None => 0,
+            Some(index) if wrap => (index as isize + step).rem_euclid(len.max(1)),
+            Some(index) => (index as isize + step).clamp(0, (len - 1).max(0)),
+        };
+        let selected = self
+            .shared()
+            .servers
+            .get(index as usize)
+            .map(|server| server.name.clone());
+        self.selected_server = selected;
+    }
+
+    // Move the selected server `step` places up or down the list
+    pub fn move_selected_server(&mut self, step: isize) {
+        if let Some(name) = &self.selected_server {
+            self.shared_mut().move_server(name, step);
+        }
+    }
+
+    // Remove the selected server and select the one that takes its place
+    pub fn delete_selected_server(&mut self) {
+        if let Some(name) = self.selected_server.clone() {
+            let replacement = self.shared_mut().delete_server(&name);
+            self.selected_server = replacement;
+        }
+    }
+
+    // Open the AddServer screen with the selected server filled in, to change its name or URL
+    pub fn edit_selected_server(&mut self) {
+        if let Some(index) = self.selected_server_index() {
+            let server = self.shared().servers[index].clone();
+            self.message_input = format!("{}:{}", server.name, server.url);
+            self.editing_server = Some(server.name);
+            self.server_error = None;
+            self.current_screen = CurrentScreen::AddServer;
+        }
+    }
+
+    // Add the server typed on the AddServer screen as "name:url", or apply the changes to the
+    // one being edited, and go back to the server list. Invalid input stays on the screen with
+    // the reason in `server_error`. Returns the old and new name of an edited server, for the
+    // tabs connected to it.
+    pub fn save_server_input(&mut self) -> Option<(String, String)> {
+        let editing = self.editing_server.clone();
+        let result = self
+            .shared_mut()
+            .save_server(&self.message_input, editing.as_deref());
+        match result {
+            Ok(name) => {
+                self.editing_server = None;
+                self.server_error = None;
+                self.message_input.clear();
+                self.current_screen = CurrentScreen::ServerSelection;
+                self.selected_server = Some(name.clone());
+                editing.map(|old| (old, name))
+            }
+            Err(e) => {
+                self.server_error = Some(e);
+                None
+            }
+        }
+    }
+
+    // Follow a rename of the server this tab is connected to
+    pub fn server_renamed(&mut self, old: &str, new_name: &str) {
+        if let Some(server) = self.server.as_mut().filter(|server| server.name == old) {
+            server.name = new_name.to_string();
+        }
+    }
+
+    // Saved servers due to be probed, see `Shared::servers_to_probe`
+    pub fn servers_to_probe(&mut self, now: Instant) -> Vec<Url> {
+        self.shared_mut().servers_to_probe(now)
+    }
+
+    pub fn set_server_status(&mut self, url: Url, result: Result<Duration, String>) {
+        self.shared_mut().set_server_status(url, result);
+    }
+
+    // Use the server picked on the server selection screen for this tab, and select it again
+    // on the next launch
+    pub fn use_selected_server(&mut self) {
+        let Some(index) = self.selected_server_index() else {
+            return;
+        };
+        let server = self.shared().servers[index].clone();
+        let mut shared = self.shared_mut();
+        if shared.config.last_server.as_ref() != Some(&server.name) {
+            shared.config.last_server = Some(server.name.clone());
+            shared.save_config();
+        }
+        drop(shared);
+        self.server = Some(server);
+    }
+
+    pub fn set_notification_mode(&mut self, mode: NotificationMode) {
+        self.notification_mode = mode;
+        let mut shared = self.shared_mut();
+        shared.config.ui.notifications = Some(mode.name().to_string());
+        shared.save_config();
+    }
+
     pub fn parse_command(&self, input: &str) -> Command {
         let input = input.trim();
 
         if input.starts_with("/") {
+            if let Some(content) = input.strip_prefix("/edit ") {
+                return Command::EditLast(content.trim().to_string());
+            }
             let parts: Vec<&str> = input.splitn(3, ' ').collect();
             match parts.as_slice() {
-                ["/name", name] if !name.is_empty() => Command::SetName(name.to_string()),
+                // Display names may contain spaces
+                ["/name", _, ..] if !input["/name".len()..].trim().is_empty() => {
+                    Command::SetName(input["/name".len()..].trim().to_string())
+                }
                 ["/list"] => Command::ListUsers,
                 ["/dm", recipient, message] if !message.is_empty() => {
                     Command::DirectMessage(recipient.to_string(), message.to_string())
                 }
+                ["/join", room] if !room.is_empty() => Command::JoinRoom(room.to_string()),
+                ["/leave"] => Command::LeaveRoom(None),
+                ["/leave", room] if !room.is_empty() => Command::LeaveRoom(Some(room.to_string())),
+                ["/rooms"] => Command::ListRooms,
+                ["/delete"] => Command::DeleteLast,
+                ["/react", emoji] if !emoji.is_empty() => Command::React(None, emoji.to_string()),
+                ["/react", id, emoji] if !emoji.is_empty() => {
+                    match id.trim_start_matches('#').parse() {
+                        Ok(id) => Command::React(Some(id), emoji.to_string()),
+                        Err(_) => Command::Unknown(input.to_string()),
+                    }
+                }
+                ["/notify", mode] => match NotificationMode::parse(mode) {
+                    Some(mode) => Command::SetNotifications(mode),
+                    None => Command::Unknown(input.to_string()),
+                },
+                ["/away"] => Command::Away(None),
+                ["/away", ..] => Command::Away(Some(input["/away".len()..].trim().to_string())),
+                ["/dnd"] => Command::DoNotDisturb,
+                ["/back"] => Command::Back,
                 ["/help"] => Command::Help,
                 _ => Command::Unknown(input.to_string()),
             }
@@ -226,3 +1482,16 @@ impl App {
         }
     }
 }
+
+// Read the message time format from `TIMESTAMP_FORMAT`, then the saved preference, falling
+// back to the default when neither is set to a valid strftime format
+fn timestamp_format(preference: Option<&str>) -> String {
+    let valid = |format: &str| !StrftimeItems::new(format).any(|item| item == Item::Error);
+    match std::env::var("TIMESTAMP_FORMAT") {
+        Ok(format) if valid(&format) => format,
+        _ => match preference {
+            Some(format) if valid(format) => format.to_string(),
+            _ => DEFAULT_TIMESTAMP_FORMAT.to_string(),
+        },
+    }
+}
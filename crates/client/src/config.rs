@@ -0,0 +1,205 @@
+//  This file contains the client's configuration: the saved servers, the server last
+//  connected to, the username last used on each server and display preferences. It is kept
+//  as TOML in the user's config directory (`~/.config/terminal_messenger/config.toml` on
+//  Linux), or at `CLIENT_CONFIG` when set, and rewritten whenever one of these changes.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+const CONFIG_DIR: &str = "terminal_messenger";
+const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub last_server: Option<String>,
+    pub servers: Vec<ServerEntry>,
+    pub usernames: BTreeMap<String, String>, // server name -> username last logged in with
+    pub ui: UiPreferences,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ServerEntry {
+    pub name: String,
+    pub url: String,
+}
+
+// Display preferences. `TIMESTAMP_FORMAT` and `NOTIFICATIONS` still override these when set.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiPreferences {
+    pub timestamp_format: Option<String>,
+    pub notifications: Option<String>, // all, mentions or off
+}
+
+impl Default for Config {
+    // The servers offered before anything has been saved
+    fn default() -> Config {
+        Config {
+            last_server: Some("default".to_string()),
+            servers: vec![
+                ServerEntry {
+                    name: "local".to_string(),
+                    url: "ws://0.0.0.0:8080".to_string(),
+                },
+                ServerEntry {
+                    name: "default".to_string(),
+                    url: "ws://autorack.proxy.rlwy.net:55901".to_string(),
+                },
+            ],
+            usernames: BTreeMap::new(),
+            ui: UiPreferences::default(),
+            path: PathBuf::new(),
+        }
+    }
+}
+
+impl Config {
+    // Load the config from `CLIENT_CONFIG` or the user's config directory, falling back to the
+    // defaults when it is missing or unreadable
+    pub fn load() -> Config {
+        let path = std::env::var("CLIENT_CONFIG")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| match dirs::config_dir() {
+                Some(dir) => dir.join(CONFIG_DIR).join(CONFIG_FILE),
+                None => PathBuf::from(CONFIG_FILE),
+            });
+        Config::open(path)
+    }
+
+    fn open(path: PathBuf) -> Config {
+        let mut config = match fs::read_to_string(&path) {
+            Ok(contents) => toml::from_str(&contents).unwrap_or_else(|e| {
+                log::error!("Ignoring unreadable config {}: {}", path.display(), e);
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        };
+        config.path = path;
+        config
+    }
+
+    // The directory the config file is in, where the client keeps its other files
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new(""))
+    }
+
+    // The saved servers whose URLs parse, in the order they were saved
+    pub fn server_urls(&self) -> Vec<(String, Url)> {
+        self.servers
+            .iter()
+            .filter_map(|server| match Url::parse(&server.url) {
+                Ok(url) => Some((server.name.clone(), url)),
+                Err(e) => {
+                    log::error!("Skipping server {} with bad URL: {}", server.name, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // Write to a temporary file next to the config and rename it into place, so a crash
+    // never leaves a half-written file behind
+    pub fn save(&self) {
+        if let Err(e) = self.write(&self.path) {
+            log::error!("Failed to save config {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn write(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = toml::to_string_pretty(self).map_err(std::io::Error::other)?;
+        let mut tmp_path = path.to_path_buf().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("config-{}", uuid::Uuid::new_v4()))
+            .join(CONFIG_FILE)
+    }
+
+    #[test]
+    fn defaults_when_missing() {
+        let path = temp_path();
+        let config = Config::open(path.clone());
+        assert_eq!(config.last_server.as_deref(), Some("default"));
+        assert_eq!(config.servers.len(), 2);
+        assert!(config.usernames.is_empty());
+        assert_eq!(config.dir(), path.parent().unwrap());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let path = temp_path();
+        let mut config = Config::open(path.clone());
+        config.last_server = Some("local".to_string());
+        config.servers.push(ServerEntry {
+            name: "home".to_string(),
+            url: "ws://127.0.0.1:9000".to_string(),
+        });
+        config
+            .usernames
+            .insert("home".to_string(), "user1".to_string());
+        config.ui.notifications = Some("mentions".to_string());
+        config.save();
+
+        let loaded = Config::open(path.clone());
+        assert_eq!(loaded.last_server.as_deref(), Some("local"));
+        assert_eq!(loaded.servers.len(), 3);
+        assert_eq!(loaded.servers[2].name, "home");
+        assert_eq!(
+            loaded.usernames.get("home").map(String::as_str),
+            Some("user1")
+        );
+        assert_eq!(loaded.ui.notifications.as_deref(), Some("mentions"));
+        assert_eq!(loaded.ui.timestamp_format, None);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn fills_in_missing_fields() {
+        let path = std::env::temp_dir().join(format!("config-{}.toml", uuid::Uuid::new_v4()));
+        fs::write(&path, "last_server = \"local\"\n").unwrap();
+        let config = Config::open(path.clone());
+        assert_eq!(config.last_server.as_deref(), Some("local"));
+        assert_eq!(config.servers.len(), 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn ignores_unreadable_config() {
+        let path = std::env::temp_dir().join(format!("config-{}.toml", uuid::Uuid::new_v4()));
+        fs::write(&path, "servers = 3").unwrap();
+        let config = Config::open(path.clone());
+        assert_eq!(config.last_server.as_deref(), Some("default"));
+        assert_eq!(config.servers.len(), 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn skips_bad_server_urls() {
+        let mut config = Config::open(temp_path());
+        config.servers.push(ServerEntry {
+            name: "broken".to_string(),
+            url: "not a url".to_string(),
+        });
+        let names: Vec<String> = config.server_urls().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(names, ["local", "default"]);
+    }
+}
@@ -1,29 +1,38 @@
 use futures_util::stream::SplitSink;
-use futures_util::{SinkExt, StreamExt};
+use futures_util::SinkExt;
 use ratatui::{
     backend::{Backend, CrosstermBackend},
     crossterm::{
-        event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind},
+        event::{
+            self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+            KeyModifiers,
+        },
         execute,
         terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     },
     Terminal,
 };
 use std::io as err_io;
+use std::time::{Duration, Instant};
 use tokio::io::{self};
 use tokio::select;
 use tokio::sync::mpsc;
 use tokio_tungstenite::tungstenite::Message;
-use url::Url;
 
 mod app;
+mod config;
+mod outbox;
+mod shared;
+mod tabs;
 mod ui;
 mod websocket;
-use crate::app::{App, Command, CurrentScreen, LoginField, MessageType};
-use crate::event::MouseEvent;
-use crate::event::MouseEventKind;
+use crate::app::{App, Command, CurrentScreen, LoginField, LoginMode, Status, QUICK_REACTIONS};
+use crate::tabs::{Tab, TabEvent, Tabs};
 use crate::ui::ui;
-use websocket::{connect_to_server, handle_websocket};
+use protocol::{normalize_room_name, MessageType};
+use websocket::{
+    connect_to_server, flush_outgoing, handle_websocket, probe_server, ReconnectEvent, WsStream,
+};
 #[tokio::main]
 async fn main() {
     env_logger::init();
@@ -42,7 +51,7 @@ async fn launch_tui() -> Result<(), Box<dyn std::error::Error>> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let mut app = App::new();
+    let app = App::new();
 
     // Create a channel for handling input events asynchronously
     let (tx, mut rx) = mpsc::channel(100);
@@ -59,7 +68,7 @@ async fn launch_tui() -> Result<(), Box<dyn std::error::Error>> {
     });
 
     // Start running the app
-    match run_app(&mut terminal, &mut app, &mut rx).await {
+    match run_app(&mut terminal, app, &mut rx).await {
         Ok(result) => result,
         Err(err) => {
             log::error!("Error running app: {:?}", err);
@@ -81,36 +90,54 @@ async fn launch_tui() -> Result<(), Box<dyn std::error::Error>> {
 
 async fn run_app<B: Backend>(
     terminal: &mut Terminal<B>,
-    app: &mut App,
+    app: App,
     rx: &mut mpsc::Receiver<Event>,
 ) -> io::Result<bool> {
-    // Set the initial state to ServerSelection
-    app.current_screen = CurrentScreen::ServerSelection;
+    // Each connection gets a tab with its own App. The first tab starts on the server
+    // selection screen.
+    let (mut tabs, mut tab_events) = Tabs::new(app);
+    tabs.active_app().current_screen = CurrentScreen::ServerSelection;
     terminal
-        .draw(|f| ui(f, app))
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        .draw(|f| ui(f, &mut tabs))
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    // Results of the background probes behind the server selection screen
+    let (probe_tx, mut probe_rx) = mpsc::unbounded_channel();
 
-    // Define `write` and `read` as Options, initially set to `None`
-    let mut write: Option<futures_util::stream::SplitSink<websocket::WsStream, Message>> = None;
-    let mut read: Option<futures_util::stream::SplitStream<websocket::WsStream>> = None;
+    // Expires typing indicators, checks for auto-away, updates the reconnect countdowns and
+    // starts server probes that are due
+    let mut tick = tokio::time::interval(Duration::from_secs(1));
 
     loop {
         select! {
-            // Handle WebSocket messages if connection exists
-            ws_res = async {
-                if let (Some(write_ref), Some(read_ref)) = (write.as_mut(), read.as_mut()) {
-                    handle_websocket(app, terminal, write_ref, read_ref).await
-                } else {
-                    Ok(())  // Skip handling if no WebSocket connection exists
+            _ = tick.tick() => {
+                let mut redraw = false;
+                for tab in tabs.iter_mut() {
+                    redraw |= tab.app.expire_typing();
+                    if tab.app.check_idle() {
+                        flush_tab(tab).await;
+                        redraw = true;
+                    }
+                    redraw |= tab.is_reconnecting();
                 }
-            }, if write.is_some() && read.is_some() => {
-                if let Err(ws_err) = ws_res {
-                    log::error!("WebSocket error: {:?}", ws_err);
-                    app.current_screen = CurrentScreen::Disconnected;
-                    write = None;  // Set streams to None on disconnection
-                    read = None;
-                    terminal.draw(|f| ui(f, app))?;
+                if start_probes(tabs.active_app(), &probe_tx) || redraw {
+                    terminal.draw(|f| ui(f, &mut tabs))?;
+                }
+            }
+
+            Some((url, result)) = probe_rx.recv() => {
+                tabs.active_app().set_server_status(url, result);
+                if let CurrentScreen::ServerSelection = tabs.active_app().current_screen {
+                    terminal.draw(|f| ui(f, &mut tabs))?;
+                }
+            }
+
+            // Frames from every tab's connection, and how their reconnects are going
+            Some(event) = tab_events.recv() => {
+                if handle_tab_event(&mut tabs, event).await {
+                    terminal.clear()?;
                 }
+                terminal.draw(|f| ui(f, &mut tabs))?;
             }
 
             // Handle user input events
@@ -119,81 +146,212 @@ async fn run_app<B: Backend>(
                     if key.kind == KeyEventKind::Release {
                         continue;
                     }
+                    // Tab hotkeys work on every screen
+                    if handle_tab_keys(key, &mut tabs) {
+                        terminal.clear()?;
+                        terminal.draw(|f| ui(f, &mut tabs))?;
+                        continue;
+                    }
+                    // The user is at the keyboard, whichever tab is on screen
+                    for tab in tabs.iter_mut() {
+                        if tab.is_connected() && tab.app.username.is_some() {
+                            tab.app.note_activity();
+                        }
+                    }
 
-                    match app.current_screen {
+                    match tabs.active_app().current_screen {
                         CurrentScreen::ServerSelection => {
-                            // Handle server selection input, and connect to the selected server afterward
-                           if handle_server_selection_input(key.code, app, &mut write, &mut read, terminal).await? {
-                                // After the user selects a server, attempt to connect
-
-                            }
+                            // Connects to the selected server, in a new tab if this one is in use
+                            handle_server_selection_input(key.code, &mut tabs).await?;
                         }
                         CurrentScreen::AddServer => {
-                           handle_add_server_input(key.code, app).await?;
+                           handle_add_server_input(key.code, &mut tabs).await?;
                         }
+                        CurrentScreen::DeletingServer => handle_deleting_server_input(key.code, tabs.active_app()),
 
                         // Handle other screens only if WebSocket streams are initialized
                         CurrentScreen::LoggingIn => {
-
-                            if let Some(ref mut write_stream) = write {
-                                handle_login_input(key.code, app, write_stream).await?;
+                            let tab = tabs.active();
+                            if let Some(ref mut write_stream) = tab.write {
+                                handle_login_input(key.code, &mut tab.app, write_stream).await?;
                             }
                         }
-                        CurrentScreen::Main => handle_main_input(key.code, app).await,
+                        CurrentScreen::Main => handle_main_input(key.code, tabs.active_app()).await,
+                        CurrentScreen::Thread => handle_thread_input(key.code, tabs.active_app()).await,
+                        // Composing works offline too; messages wait in the outbox
                         CurrentScreen::ComposingMessage => {
-                            if let Some(ref mut write_stream) = write {
-                                handle_composing_message_input(key.code, app, write_stream).await?;
-                            }
+                            handle_composing_message_input(key.code, tabs.active_app()).await?;
                         }
                         CurrentScreen::SetUser => {
-                            if let Some(ref mut write_stream) = write {
-                                handle_set_user_input(key.code, app, write_stream).await?;
+                            let tab = tabs.active();
+                            if let Some(ref mut write_stream) = tab.write {
+                                handle_set_user_input(key.code, &mut tab.app, write_stream).await?;
                             }
                         }
-                        CurrentScreen::HelpMenu => handle_help_menu_input(key.code, app).await?,
+                        CurrentScreen::HelpMenu => handle_help_menu_input(key.code, tabs.active_app()).await?,
                         CurrentScreen::Exiting => {
-                            if handle_exiting_input(key.code, app).await? {
+                            if handle_exiting_input(key.code, tabs.active_app()).await? {
                                 break Ok(false);
                             }
                         }
                         CurrentScreen::ExitingLoggingIn => {
-                            if handle_exiting_logging_in_input(key.code, app).await? {
+                            if handle_exiting_logging_in_input(key.code, tabs.active_app()).await? {
                                 break Ok(false);
                             }
                         }
+                        // Keys wait until the server has answered the resume request
+                        CurrentScreen::Resuming => {}
                         CurrentScreen::Disconnected => {
-                                handle_disconnected_input(key.code, app, terminal, &mut write, &mut read).await?;
+                                handle_disconnected_input(key.code, tabs.active());
                         }
                     }
 
-                    terminal.draw(|f| ui(f, app)).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    start_probes(tabs.active_app(), &probe_tx);
+
+                    // Switching to another server cancels the background reconnect
+                    let tab = tabs.active();
+                    if matches!(
+                        tab.app.current_screen,
+                        CurrentScreen::ServerSelection | CurrentScreen::AddServer | CurrentScreen::DeletingServer
+                    ) {
+                        tab.stop_reconnecting();
+                    }
+
+                    // Send anything the App queued while handling the key
+                    tab.app.mark_read();
+                    flush_tab(tab).await;
+
+                    terminal.draw(|f| ui(f, &mut tabs)).map_err(io::Error::other)?;
                 } else if let Event::Resize(_, _) = event {
-                    terminal.draw(|f| ui(f, app)).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                    terminal.draw(|f| ui(f, &mut tabs)).map_err(io::Error::other)?;
+                }
+            }
+        }
+    }
+}
+
+// Alt+1..9 jumps to a tab, Alt+Left/Right cycles through them and Alt+w closes the one on
+// screen. Returns whether the key was one of these.
+fn handle_tab_keys(key: KeyEvent, tabs: &mut Tabs) -> bool {
+    if !key.modifiers.contains(KeyModifiers::ALT) {
+        return false;
+    }
+    match key.code {
+        KeyCode::Char(c @ '1'..='9') => tabs.switch_to(c as usize - '1' as usize),
+        KeyCode::Left => tabs.cycle(-1),
+        KeyCode::Right => tabs.cycle(1),
+        KeyCode::Char('w') => tabs.close_active(),
+        _ => return false,
+    }
+    true
+}
+
+// Apply a frame from one of the tabs' connections, or news from a reconnect. Events from
+// connections that have since been replaced are dropped. Returns whether the screen should be
+// cleared, as it is when a session starts again.
+async fn handle_tab_event(tabs: &mut Tabs, event: TabEvent) -> bool {
+    match event {
+        TabEvent::Received(source, message) => {
+            let Some(tab) = tabs.by_source(source) else {
+                return false;
+            };
+            let Some(write) = tab.write.as_mut() else {
+                return false;
+            };
+            let connected = match handle_websocket(&mut tab.app, write, message).await {
+                Ok(connected) => connected,
+                Err(ws_err) => {
+                    log::error!("WebSocket error: {:?}", ws_err);
+                    tab.app.requeue_pending_messages();
+                    tab.app.current_screen = CurrentScreen::Disconnected;
+                    false
+                }
+            };
+            if !connected {
+                tab.detach();
+                tab.app.online = false;
+                // A logged in user is reconnected automatically
+                if tab.app.can_resume() {
+                    tab.reconnect(false);
+                }
+            }
+            false
+        }
+        // Follow the background reconnect, resuming the session once it gets through
+        TabEvent::Reconnect(source, event) => {
+            let Some(tab) = tabs.by_source(source) else {
+                return false;
+            };
+            match event {
+                ReconnectEvent::Waiting { attempt, retry_at } => {
+                    tab.app.reconnect_attempt = attempt;
+                    tab.app.reconnect_at = Some(retry_at);
+                    false
+                }
+                ReconnectEvent::Connected(ws_stream) => {
+                    tab.stop_reconnecting();
+                    if let Err(e) = begin_session(tab, *ws_stream).await {
+                        // The new connection dropped straight away; keep trying
+                        log::error!("Failed to start session: {:?}", e);
+                        tab.detach();
+                        tab.reconnect(false);
+                    }
+                    true
                 }
             }
         }
     }
 }
 
-async fn handle_add_server_input(key: KeyCode, app: &mut App) -> io::Result<bool> {
+// Send anything the tab's App queued. A failed send means the connection is gone, which the
+// tab's connection reports in turn.
+async fn flush_tab(tab: &mut Tab) {
+    if let Some(ref mut write_stream) = tab.write {
+        if let Err(e) = flush_outgoing(&mut tab.app, write_stream).await {
+            log::error!("Failed to send message: {:?}", e);
+            tab.app.requeue_pending_messages();
+        }
+    }
+}
+
+// Probe the saved servers that are due while the server selection screen is open. Returns
+// whether any were started, as the list then shows them being checked.
+fn start_probes(
+    app: &mut App,
+    probe_tx: &mpsc::UnboundedSender<(url::Url, Result<Duration, String>)>,
+) -> bool {
+    if !matches!(app.current_screen, CurrentScreen::ServerSelection) {
+        return false;
+    }
+    let due = app.servers_to_probe(Instant::now());
+    let started = !due.is_empty();
+    for url in due {
+        probe_server(url, probe_tx.clone());
+    }
+    started
+}
+
+async fn handle_add_server_input(key: KeyCode, tabs: &mut Tabs) -> io::Result<bool> {
+    let app = tabs.active_app();
     match key {
+        // Save the server typed as "name:url", or show why it was rejected
         KeyCode::Enter => {
-            if app.message_input.contains(':') {
-                // Add a new server if the input contains "name:url"
-                let parts: Vec<&str> = app.message_input.splitn(2, ':').collect();
-                if let Ok(url) = Url::parse(parts[1]) {
-                    app.servers.insert(parts[0].to_string(), url);
-                }
-                app.message_input.clear();
+            if let Some((old, new_name)) = app.save_server_input() {
+                tabs.server_edited(&old, &new_name);
             }
         }
         KeyCode::Backspace => {
             app.message_input.pop(); // Handle backspace to delete characters
+            app.server_error = None;
         }
         KeyCode::Char(c) => {
             app.message_input.push(c); // Add character to input
+            app.server_error = None;
         }
         KeyCode::Esc => {
+            app.message_input.clear();
+            app.editing_server = None;
+            app.server_error = None;
             app.current_screen = CurrentScreen::ServerSelection; // Cancel add_server input and go back
         }
 
@@ -203,140 +361,91 @@ async fn handle_add_server_input(key: KeyCode, app: &mut App) -> io::Result<bool
     Ok(false) // Return false if no valid server is selected
 }
 
-async fn handle_server_selection_input(
-    key: KeyCode,
-    app: &mut App,
-    write: &mut Option<futures_util::stream::SplitSink<websocket::WsStream, Message>>,
-    read: &mut Option<futures_util::stream::SplitStream<websocket::WsStream>>,
-    terminal: &mut Terminal<impl Backend>,
-) -> io::Result<bool> {
+// A connected tab that opened the server list goes back to its chat
+fn leave_server_selection(tab: &mut Tab) {
+    if tab.is_connected() {
+        tab.app.current_screen = tab.app.chat_screen();
+        tab.app.message_input.clear();
+    }
+}
+
+async fn handle_server_selection_input(key: KeyCode, tabs: &mut Tabs) -> io::Result<()> {
+    let app = tabs.active_app();
+    app.server_error = None;
     match key {
-        KeyCode::Enter => {
-            if let Some(selected_server) = app.servers.get(app.selected_server.as_ref().unwrap()) {
-                // Disconnect the current WebSocket streams
-                *write = None;
-                *read = None;
-
-                // Establish a new WebSocket connection with the selected server
-                let ws_stream = connect_to_server(app)
-                    .await
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-                // Split the new WebSocket stream into `write` and `read`
-                let (new_write, new_read) = ws_stream.split();
-                *write = Some(new_write);
-                *read = Some(new_read);
-
-                // Transition to the login screen after connection
-                app.current_screen = CurrentScreen::LoggingIn;
-                app.message_input.clear();
-
-                // Reset login input fields
-                app.username = None; // Clear any existing username
-                app.password = None; // Clear any existing password
-                app.current_login_field = LoginField::Username; // Start with the username field
-
-                terminal
-                    .draw(|f| ui(f, app))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-
-                return Ok(true);
+        KeyCode::Enter if app.selected_server_index().is_some() => {
+            // A server that is already open in a tab is shown there rather than joined twice
+            let name = app.selected_server.clone().unwrap_or_default();
+            if let Some(index) = tabs.find_server(&name) {
+                leave_server_selection(tabs.active());
+                tabs.switch_to(index);
+                return Ok(());
             }
-        }
-
-        KeyCode::Up => {
-            if let Some(selected_server) = &app.selected_server {
-                let server_names: Vec<&String> = app.servers.keys().collect();
 
-                for (i, name) in server_names.iter().enumerate() {
-                    if *name == selected_server {
-                        if i > 0 {
-                            let new_selected_server_name =
-                                server_names.get(i - 1).expect("Failed to get server name");
-                            app.selected_server = Some(new_selected_server_name.to_string());
-                            break;
-                        }
+            // Establish a new WebSocket connection with the selected server. If it fails, the
+            // reason is shown under the list and the tab is left as it was.
+            let app = tabs.active_app();
+            let ws_stream = match connect_to_server(app).await {
+                Ok(ws_stream) => ws_stream,
+                Err(e) => {
+                    app.server_error = Some(format!("Could not connect to {}: {}", name, e));
+                    if let Some(url) = app.selected_server_url() {
+                        app.set_server_status(url, Err(e.to_string()));
                     }
+                    return Ok(());
                 }
-            } else {
-                if let Some(first_server_name) = app.servers.keys().next() {
-                    app.selected_server = Some(first_server_name.to_string());
-                }
+            };
+
+            // A tab that is connected keeps its server, and the new connection gets a tab of
+            // its own
+            if tabs.active().is_connected() {
+                leave_server_selection(tabs.active());
+                tabs.open();
             }
+            let tab = tabs.active();
+            tab.attach(ws_stream);
+
+            // Transition to the login screen after connection
+            let app = &mut tab.app;
+            app.current_screen = CurrentScreen::LoggingIn;
+            app.message_input.clear();
+
+            // Start from a clean history for the new server
+            app.reset_history();
+            app.use_selected_server();
+
+            // Reset login input fields
+            app.login_mode = LoginMode::SignIn;
+            app.start_login();
         }
 
-        KeyCode::Down => {
-            if let Some(selected_server) = &app.selected_server {
-                let server_names: Vec<&String> = app.servers.keys().collect();
+        KeyCode::Up => app.select_server(-1, false),
+        KeyCode::Down => app.select_server(1, false),
+        KeyCode::Tab => app.select_server(1, true),
 
-                for (i, name) in server_names.iter().enumerate() {
-                    if *name == selected_server {
-                        if i < app.servers.len() - 1 {
-                            let new_selected_server_name =
-                                server_names.get(i + 1).expect("Failed to get server name");
-                            app.selected_server = Some(new_selected_server_name.to_string());
-                            break;
-                        }
-                    }
-                }
-            } else {
-                if let Some(first_server_name) = app.servers.keys().next() {
-                    app.selected_server = Some(first_server_name.to_string());
-                }
-            }
+        // Reorder, edit or delete the selected server
+        KeyCode::Char('K') => app.move_selected_server(-1),
+        KeyCode::Char('J') => app.move_selected_server(1),
+        KeyCode::Char('e') => app.edit_selected_server(),
+        KeyCode::Char('d') if app.selected_server_index().is_some() => {
+            app.current_screen = CurrentScreen::DeletingServer; // Ask before deleting
         }
 
         KeyCode::Char('n') => {
             app.current_screen = CurrentScreen::AddServer; // Transition to add server screen
             app.message_input.clear(); // Clear any input
-
-            terminal
-                .draw(|f| ui(f, app))
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-
-            //return Ok(true);
+            app.editing_server = None;
+            app.server_error = None;
         }
         KeyCode::Esc => {
-            if write.is_some() && read.is_some() {
-                // Check if there is an active server connection
-                app.current_screen = CurrentScreen::Main; // Transition to the main screen
-                app.message_input.clear(); // Clear any input
-
-                terminal
-                    .draw(|f| ui(f, app))
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
-
-                return Ok(true);
-            } else {
-                // If no active connection exists, clear input and stay on server selection screen
-                app.message_input.clear();
-            }
-        }
-        KeyCode::Tab => {
-            if let Some(selected_server) = &app.selected_server {
-                let server_names: Vec<&String> = app.servers.keys().collect();
-                let current_index = server_names
-                    .iter()
-                    .position(|name| *name == selected_server);
-
-                if let Some(index) = current_index {
-                    let next_index = (index + 1) % app.servers.len();
-                    let new_selected_server_name = server_names
-                        .get(next_index)
-                        .expect("Failed to get server name");
-                    app.selected_server = Some(new_selected_server_name.to_string());
-                } else if let Some(first_server_name) = app.servers.keys().next() {
-                    app.selected_server = Some(first_server_name.to_string());
-                }
-            } else if let Some(first_server_name) = app.servers.keys().next() {
-                app.selected_server = Some(first_server_name.to_string());
-            }
+            // Back to the chat if this tab has a connection, otherwise stay on the list
+            app.message_input.clear();
+            leave_server_selection(tabs.active());
         }
-
         _ => {}
     }
 
-    Ok(false) // Return false if no valid server is selected
+    Ok(())
 }
 
 async fn handle_login_input(
@@ -366,39 +475,32 @@ async fn handle_login_input(
                             app.password = Some(app.message_input.clone());
                             app.message_input.clear();
 
-                            // If both fields are filled, submit the login request
-                            if let (Some(username), Some(password)) = (&app.username, &app.password)
-                            {
-                                let auth_message = MessageType::SystemMessage(format!(
-                                    "{}:{}",
-                                    username, password
-                                ));
-                                write
-                                    .send(Message::Text(
-                                        serde_json::to_string(&auth_message).unwrap(),
-                                    ))
-                                    .await
-                                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-                                // Store username as staging and reset for a retry if needed
-                                app.staging_username = Some(username.clone());
+                            match app.login_mode {
+                                LoginMode::SignIn => submit_credentials(app, write).await?,
+                                LoginMode::Register => {
+                                    app.current_login_field = LoginField::InviteCode; // Move to invite code field
+                                    app.is_typing = false;
+                                    app.messages.push(MessageType::SystemMessage(
+                                        "Enter an invite code, or leave it empty if the server does not need one:"
+                                            .to_string(),
+                                    ));
+                                }
                             }
-
-                            // Reset after submission
-                            app.username = None;
-                            app.password = None;
-                            app.current_login_field = LoginField::Username; // Reset to username field
-                            app.is_typing = false; // Stop typing
                         }
                     }
+                    LoginField::InviteCode => {
+                        // The invite code is optional, so an empty submission is allowed
+                        app.invite_code =
+                            Some(app.message_input.clone()).filter(|code| !code.trim().is_empty());
+                        app.message_input.clear();
+                        submit_credentials(app, write).await?;
+                    }
                 }
             }
 
             // Handle Backspace key press only when typing
             KeyCode::Backspace => {
-                if !app.message_input.is_empty() {
-                    app.message_input.pop();
-                }
+                app.message_input.pop();
             }
 
             // Handle character input while typing
@@ -424,12 +526,24 @@ async fn handle_login_input(
 
             // Switch between fields using Tab
             KeyCode::Tab => {
-                app.current_login_field = match app.current_login_field {
-                    LoginField::Username => LoginField::Password,
-                    LoginField::Password => LoginField::Username,
+                app.current_login_field = match (&app.current_login_field, &app.login_mode) {
+                    (LoginField::Username, _) => LoginField::Password,
+                    (LoginField::Password, LoginMode::Register) => LoginField::InviteCode,
+                    (LoginField::Password, LoginMode::SignIn) | (LoginField::InviteCode, _) => {
+                        LoginField::Username
+                    }
                 };
             }
 
+            // Toggle between signing in and creating an account
+            KeyCode::Char('c') => {
+                app.login_mode = match app.login_mode {
+                    LoginMode::SignIn => LoginMode::Register,
+                    LoginMode::Register => LoginMode::SignIn,
+                };
+                app.reset_login_fields();
+            }
+
             // Quit the application with 'q' when not typing
             KeyCode::Char('q') => {
                 // Set screen to Exiting
@@ -443,8 +557,68 @@ async fn handle_login_input(
     Ok(())
 }
 
+// Send either a login or a registration request with the collected fields
+async fn submit_credentials(
+    app: &mut App,
+    write: &mut SplitSink<websocket::WsStream, Message>,
+) -> io::Result<()> {
+    // If both fields are filled, submit the request
+    if let (Some(username), Some(password)) = (&app.username, &app.password) {
+        let auth_message = match app.login_mode {
+            LoginMode::SignIn => MessageType::Login {
+                username: username.clone(),
+                password: password.clone(),
+            },
+            LoginMode::Register => MessageType::Register {
+                username: username.clone(),
+                password: password.clone(),
+                invite_code: app.invite_code.clone(),
+            },
+        };
+        write
+            .send(Message::Text(auth_message.to_json()))
+            .await
+            .map_err(io::Error::other)?;
+
+        // Store username as staging and reset for a retry if needed
+        app.staging_username = Some(username.clone());
+    } else {
+        app.messages.push(MessageType::SystemMessage(
+            "Both a username and a password are required.".to_string(),
+        ));
+    }
+
+    // Reset after submission
+    app.reset_login_fields();
+    Ok(())
+}
+
+// While a message is selected the arrow keys move the selection and the number keys
+// toggle reactions on it. Returns whether the key was used.
+fn handle_selection_input(key: KeyCode, app: &mut App) -> bool {
+    if app.selected_message.is_none() {
+        return false;
+    }
+    match key {
+        KeyCode::Up => app.move_selection(true),
+        KeyCode::Down => app.move_selection(false),
+        KeyCode::Esc | KeyCode::Char('r') => app.selected_message = None,
+        KeyCode::Char(c @ '1'..='6') => {
+            let emoji = QUICK_REACTIONS[c as usize - '1' as usize];
+            app.toggle_reaction(None, emoji);
+        }
+        _ => return false,
+    }
+    true
+}
+
 async fn handle_main_input(key: KeyCode, app: &mut App) {
+    if handle_selection_input(key, app) {
+        return;
+    }
+
     match key {
+        KeyCode::Enter if app.selected_message.is_some() => app.open_selected_thread(),
         KeyCode::Enter => {
             app.current_screen = CurrentScreen::ComposingMessage;
             app.message_input.clear();
@@ -463,70 +637,77 @@ async fn handle_main_input(key: KeyCode, app: &mut App) {
             app.current_screen = CurrentScreen::ServerSelection;
             app.message_input.clear();
         }
+        KeyCode::Tab => app.next_room(),
+        KeyCode::Char('r') => app.move_selection(true),
         KeyCode::Up => app.scroll_up(),
         KeyCode::Down => app.scroll_down(),
         _ => {}
     }
 }
-async fn handle_composing_message_input(
-    key: KeyCode,
-    app: &mut App,
-    write: &mut futures_util::stream::SplitSink<websocket::WsStream, Message>,
-) -> io::Result<()> {
+
+async fn handle_thread_input(key: KeyCode, app: &mut App) {
+    if handle_selection_input(key, app) {
+        return;
+    }
+
     match key {
         KeyCode::Enter => {
+            app.current_screen = CurrentScreen::ComposingMessage;
+            app.message_input.clear();
+        }
+        KeyCode::Esc => app.close_thread(),
+        KeyCode::Char('r') => app.move_selection(true),
+        KeyCode::Up => app.scroll_up(),
+        KeyCode::Down => app.scroll_down(),
+        _ => {}
+    }
+}
+
+async fn handle_composing_message_input(key: KeyCode, app: &mut App) -> io::Result<()> {
+    match key {
+        KeyCode::Enter => {
+            // Requests are sent by the main loop, or once the session is back when offline
             let user_input = app.message_input.clone();
             match app.parse_command(&user_input) {
-                Command::SetName(name) => {
-                    let cmd = MessageType::Command {
-                        name: "name".to_string(),
-                        args: vec![name.clone()],
-                    };
-                    write
-                        .send(Message::Text(serde_json::to_string(&cmd).unwrap()))
-                        .await
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-
-                    app.set_username(name);
+                // Sets the account's display name; the username stays the same
+                Command::SetName(name) => app.send_command("name", vec![name]),
+                Command::ListUsers => app.send_command("list", vec![]),
+                Command::DirectMessage(recipient, message) => {
+                    app.send_command("DirectMessage", vec![recipient, message])
                 }
-                Command::ListUsers => {
-                    let cmd = MessageType::Command {
-                        name: "list".to_string(),
-                        args: vec![],
-                    };
-                    write
-                        .send(Message::Text(serde_json::to_string(&cmd).unwrap()))
-                        .await
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                Command::JoinRoom(room) => {
+                    app.pending_join = normalize_room_name(&room);
+                    app.send_command("join", vec![room]);
                 }
-                Command::DirectMessage(recipient, message) => {
-                    let cmd = MessageType::Command {
-                        name: "DirectMessage".to_string(),
-                        args: vec![recipient.clone(), message.clone()],
-                    };
-                    write
-                        .send(Message::Text(serde_json::to_string(&cmd).unwrap()))
-                        .await
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+                Command::LeaveRoom(room) => {
+                    let room = room.unwrap_or_else(|| app.current_room.clone());
+                    app.send_command("leave", vec![room]);
                 }
+                Command::ListRooms => app.send_command("rooms", vec![]),
+                Command::EditLast(content) => app.edit_last_message(content),
+                Command::DeleteLast => app.delete_last_message(),
+                Command::React(id, emoji) => app.toggle_reaction(id, &emoji),
+                Command::SetNotifications(mode) => {
+                    app.set_notification_mode(mode);
+                    app.messages.push(MessageType::SystemMessage(format!(
+                        "Notification sound plays for {}.",
+                        mode.describe()
+                    )));
+                }
+                Command::Away(reason) => app.set_status(Status::Away, reason),
+                Command::DoNotDisturb => app.set_status(Status::DoNotDisturb, None),
+                Command::Back => app.set_status(Status::Online, None),
                 Command::Help => {
                     app.current_screen = CurrentScreen::HelpMenu;
                 }
-                Command::Unknown(input) => {
-                    let msg = MessageType::ChatMessage {
-                        sender: app.username.clone().unwrap_or_else(|| "You".to_string()),
-                        content: input.clone(),
-                    };
-                    app.messages.push(msg.clone());
-                    write
-                        .send(Message::Text(serde_json::to_string(&msg).unwrap()))
-                        .await
-                        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-                }
+                Command::Unknown(input) => app.send_message(input),
             }
 
             app.message_input.clear();
-            app.current_screen = CurrentScreen::Main;
+            app.stop_composing();
+            if let CurrentScreen::ComposingMessage = app.current_screen {
+                app.current_screen = app.chat_screen();
+            }
             return Ok(());
         }
         KeyCode::Up | KeyCode::PageUp => {
@@ -542,42 +723,33 @@ async fn handle_composing_message_input(
             return Ok(());
         }
         KeyCode::Esc => {
-            app.current_screen = CurrentScreen::Main;
+            app.stop_composing();
+            app.current_screen = app.chat_screen();
             return Ok(());
         }
-        KeyCode::Char(c) => app.message_input.push(c),
+        KeyCode::Char(c) => {
+            app.message_input.push(c);
+            app.note_composing();
+        }
         _ => {}
     }
 
     Ok(())
 }
 
-async fn handle_disconnected_input(
-    key: KeyCode,
-    app: &mut App,
-    terminal: &mut Terminal<impl Backend>,
-    write: &mut Option<futures_util::stream::SplitSink<websocket::WsStream, Message>>,
-    read: &mut Option<futures_util::stream::SplitStream<websocket::WsStream>>,
-) -> io::Result<()> {
+fn handle_disconnected_input(key: KeyCode, tab: &mut Tab) {
+    let app = &mut tab.app;
     match key {
         KeyCode::Char('r') => {
-            // Attempt to reconnect to the selected server
-            if let Ok(ws_stream) = websocket::connect_to_server(app).await {
-                let (new_write, new_read) = ws_stream.split();
-                *write = Some(new_write);
-                *read = Some(new_read);
-
-                // Clear the terminal and force a full redraw
-                terminal.clear()?;
-                app.current_screen = CurrentScreen::Main; // Back to main screen after reconnection
-                terminal.draw(|f| crate::ui::ui(f, app))?;
-            } else {
-                // Handle reconnection failure, maybe push a system message to the app
-                app.messages.push(MessageType::SystemMessage(
-                    "Reconnection failed. Please check the server.".to_string(),
-                ));
-                terminal.draw(|f| crate::ui::ui(f, app))?;
-            }
+            // Try again straight away, replacing any reconnect already waiting
+            tab.reconnect(true);
+        }
+        KeyCode::Char('s') => {
+            app.current_screen = CurrentScreen::ServerSelection;
+        }
+        // Back to the chat while reconnecting; messages written meanwhile are queued
+        KeyCode::Char('c') if app.can_resume() => {
+            app.current_screen = app.chat_screen();
         }
         KeyCode::Char('q') => {
             // Quit the app gracefully
@@ -585,7 +757,30 @@ async fn handle_disconnected_input(
         }
         _ => {}
     }
+}
 
+// Authenticate a new connection to the tab's server: resume the session with its token,
+// or log in from scratch if there is none
+async fn begin_session(tab: &mut Tab, ws_stream: WsStream) -> io::Result<()> {
+    tab.attach(ws_stream);
+    let app = &mut tab.app;
+    app.reconnect_attempt = 0;
+    app.reconnect_at = None;
+    match (app.resume_request(), tab.write.as_mut()) {
+        (Some(resume), Some(write)) => {
+            write
+                .send(Message::Text(resume.to_json()))
+                .await
+                .map_err(io::Error::other)?;
+            app.current_screen = CurrentScreen::Resuming;
+        }
+        _ => {
+            app.reset_history();
+            app.login_mode = LoginMode::SignIn;
+            app.start_login();
+            app.current_screen = CurrentScreen::LoggingIn;
+        }
+    }
     Ok(())
 }
 
@@ -596,29 +791,26 @@ async fn handle_set_user_input(
 ) -> io::Result<()> {
     match key {
         KeyCode::Enter => {
-            // Set the username and switch back to the main screen
-            let username = app.message_input.clone();
-            app.set_username(username.clone());
+            // Ask the server to set the account's display name and switch back to the main
+            // screen
+            let display_name = app.message_input.clone();
 
             let cmd = MessageType::Command {
                 name: "name".to_string(),
-                args: vec![username.clone()],
+                args: vec![display_name],
             };
-            if let Err(e) = write
-                .send(Message::Text(serde_json::to_string(&cmd).unwrap()))
-                .await
-            {
+            if let Err(e) = write.send(Message::Text(cmd.to_json())).await {
                 log::error!("Failed to send command: {:?}", e);
             }
 
             app.current_screen = CurrentScreen::Main; // Go back to the main screen
-            app.message_input.clear(); // Clear input after setting username
+            app.message_input.clear(); // Clear input after setting the display name
         }
         KeyCode::Backspace => {
             app.message_input.pop(); // Handle backspace to delete last character
         }
         KeyCode::Esc => {
-            app.current_screen = CurrentScreen::Main; // Cancel username input and go back
+            app.current_screen = CurrentScreen::Main; // Cancel display name input and go back
         }
         KeyCode::Char(c) => {
             app.message_input.push(c); // Add typed character to input
@@ -629,12 +821,25 @@ async fn handle_set_user_input(
 }
 
 async fn handle_help_menu_input(_key: KeyCode, app: &mut App) -> io::Result<()> {
-    // pressing any key will exit help menu and go back to main screen
-    app.current_screen = CurrentScreen::Main;
+    // pressing any key will exit help menu and go back to the chat (or the open thread)
+    app.current_screen = app.chat_screen();
 
     Ok(())
 }
 
+fn handle_deleting_server_input(key: KeyCode, app: &mut App) {
+    match key {
+        KeyCode::Char('y') => {
+            app.delete_selected_server();
+            app.current_screen = CurrentScreen::ServerSelection;
+        }
+        KeyCode::Char('n') | KeyCode::Char('q') | KeyCode::Esc => {
+            app.current_screen = CurrentScreen::ServerSelection;
+        }
+        _ => {}
+    }
+}
+
 async fn handle_exiting_input(key: KeyCode, app: &mut App) -> io::Result<bool> {
     match key {
         KeyCode::Char('y') => {
@@ -0,0 +1,251 @@
+//  This file contains the outbox, which holds every chat message the server has not
+//  acknowledged yet, including those written while the client is offline. They are saved to
+//  `outbox.json` next to the config file (or `OUTBOX_FILE` when set) so they survive a
+//  dropped connection or a restart, and are sent again in order once the user's session on
+//  the same server is restored. A message leaves the outbox when the server acknowledges or
+//  rejects it.
+use protocol::MessageId;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_OUTBOX_FILE: &str = "outbox.json";
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct QueuedMessage {
+    pub server: String,   // name of the server it is for
+    pub username: String, // account that wrote it
+    pub nonce: String,    // matches the pending message shown in the chat
+    pub room: String,
+    pub content: String,
+    pub reply_to: Option<MessageId>,
+}
+
+pub struct Outbox {
+    path: PathBuf,
+    messages: Vec<QueuedMessage>, // oldest first
+}
+
+impl Outbox {
+    // Load the outbox from `OUTBOX_FILE`, or from `dir` (the config directory) when unset,
+    // starting empty if it is missing or unreadable
+    pub fn load(dir: &Path) -> Outbox {
+        let path = std::env::var("OUTBOX_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| dir.join(DEFAULT_OUTBOX_FILE));
+        Outbox::open(path)
+    }
+
+    fn open(path: PathBuf) -> Outbox {
+        let messages = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                log::error!("Ignoring unreadable outbox {}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(_) => Vec::new(),
+        };
+        Outbox { path, messages }
+    }
+
+    pub fn push(&mut self, message: QueuedMessage) {
+        self.messages.push(message);
+        self.save();
+    }
+
+    // The messages `username` queued for `server`, oldest first. They stay in the outbox
+    // until `remove` is called for each.
+    pub fn queued(&self, server: &str, username: &str) -> Vec<QueuedMessage> {
+        self.messages
+            .iter()
+            .filter(|message| message.server == server && message.username == username)
+            .cloned()
+            .collect()
+    }
+
+    // Forget a message once the server has acknowledged or rejected it
+    pub fn remove(&mut self, nonce: &str) {
+        let count = self.messages.len();
+        self.messages.retain(|message| message.nonce != nonce);
+        if self.messages.len() != count {
+            self.save();
+        }
+    }
+
+    // Keep messages queued for a server that has been renamed
+    pub fn rename_server(&mut self, server: &str, new_name: &str) {
+        let mut renamed = false;
+        for message in self.messages.iter_mut().filter(|m| m.server == server) {
+            message.server = new_name.to_string();
+            renamed = true;
+        }
+        if renamed {
+            self.save();
+        }
+    }
+
+    // Drop the messages queued for a server that has been deleted, so they are not sent to
+    // another server saved later under the same name
+    pub fn remove_server(&mut self, server: &str) {
+        let count = self.messages.len();
+        self.messages.retain(|message| message.server != server);
+        if self.messages.len() != count {
+            self.save();
+        }
+    }
+
+    // Write to a temporary file next to the outbox and rename it into place, removing the
+    // file once nothing is queued
+    fn save(&self) {
+        let result = if self.messages.is_empty() {
+            match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            }
+        } else {
+            self.write()
+        };
+        if let Err(e) = result {
+            log::error!("Failed to save outbox {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn write(&self) -> std::io::Result<()> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let contents =
+            serde_json::to_string_pretty(&self.messages).map_err(std::io::Error::other)?;
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, contents)?;
+        fs::rename(&tmp_path, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An outbox in its own temporary directory, which is removed again when dropped
+    struct TempOutbox(Outbox);
+
+    impl Drop for TempOutbox {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.path.parent() {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    impl std::ops::Deref for TempOutbox {
+        type Target = Outbox;
+        fn deref(&self) -> &Outbox {
+            &self.0
+        }
+    }
+
+    impl std::ops::DerefMut for TempOutbox {
+        fn deref_mut(&mut self) -> &mut Outbox {
+            &mut self.0
+        }
+    }
+
+    fn temp_outbox() -> TempOutbox {
+        let dir = std::env::temp_dir().join(format!("outbox-{}", uuid::Uuid::new_v4()));
+        TempOutbox(Outbox::open(dir.join(DEFAULT_OUTBOX_FILE)))
+    }
+
+    fn message(server: &str, username: &str, nonce: &str) -> QueuedMessage {
+        QueuedMessage {
+            server: server.to_string(),
+            username: username.to_string(),
+            nonce: nonce.to_string(),
+            room: "#general".to_string(),
+            content: format!("message {nonce}"),
+            reply_to: None,
+        }
+    }
+
+    fn nonces(messages: &[QueuedMessage]) -> Vec<&str> {
+        messages.iter().map(|m| m.nonce.as_str()).collect()
+    }
+
+    #[test]
+    fn queues_messages_per_server_and_user() {
+        let mut outbox = temp_outbox();
+        outbox.push(message("one", "user1", "a"));
+        outbox.push(message("two", "user1", "b"));
+        outbox.push(message("one", "user2", "c"));
+        outbox.push(message("one", "user1", "d"));
+
+        assert_eq!(nonces(&outbox.queued("one", "user1")), ["a", "d"]);
+        assert_eq!(nonces(&outbox.queued("two", "user1")), ["b"]);
+        assert!(outbox.queued("two", "user2").is_empty());
+        // Queued messages stay until removed
+        assert_eq!(outbox.queued("one", "user1").len(), 2);
+    }
+
+    #[test]
+    fn removes_acknowledged_messages() {
+        let mut outbox = temp_outbox();
+        outbox.push(message("one", "user1", "a"));
+        outbox.push(message("one", "user1", "b"));
+
+        outbox.remove("a");
+        outbox.remove("unknown");
+        assert_eq!(nonces(&outbox.queued("one", "user1")), ["b"]);
+    }
+
+    #[test]
+    fn renames_servers() {
+        let mut outbox = temp_outbox();
+        outbox.push(message("one", "user1", "a"));
+        outbox.push(message("two", "user1", "b"));
+
+        outbox.rename_server("one", "three");
+        assert!(outbox.queued("one", "user1").is_empty());
+        assert_eq!(nonces(&outbox.queued("three", "user1")), ["a"]);
+        assert_eq!(nonces(&outbox.queued("two", "user1")), ["b"]);
+    }
+
+    #[test]
+    fn removes_deleted_servers() {
+        let mut outbox = temp_outbox();
+        outbox.push(message("one", "user1", "a"));
+        outbox.push(message("two", "user1", "b"));
+        outbox.push(message("one", "user2", "c"));
+
+        outbox.remove_server("one");
+        assert!(outbox.queued("one", "user1").is_empty());
+        assert!(outbox.queued("one", "user2").is_empty());
+        assert_eq!(nonces(&outbox.queued("two", "user1")), ["b"]);
+
+        let reopened = Outbox::open(outbox.path.clone());
+        assert_eq!(nonces(&reopened.queued("two", "user1")), ["b"]);
+    }
+
+    #[test]
+    fn persists_until_empty() {
+        let mut outbox = temp_outbox();
+        outbox.push(message("one", "user1", "a"));
+        outbox.push(message("one", "user1", "b"));
+        outbox.remove("a");
+
+        let reopened = Outbox::open(outbox.path.clone());
+        let queued = reopened.queued("one", "user1");
+        assert_eq!(nonces(&queued), ["b"]);
+        assert_eq!(queued[0].content, "message b");
+
+        outbox.remove("b");
+        assert!(!outbox.path.exists());
+    }
+
+    #[test]
+    fn ignores_unreadable_outbox() {
+        let path = std::env::temp_dir().join(format!("outbox-{}.json", uuid::Uuid::new_v4()));
+        fs::write(&path, "not json").unwrap();
+        let outbox = Outbox::open(path.clone());
+        assert!(outbox.messages.is_empty());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+//  This file contains the state shared by every tab: the saved servers and the latest probe
+//  of each, the config file they are saved in and the outbox of messages not yet sent.
+//  Each tab's `App` holds a handle to the same `Shared`.
+use crate::config::{Config, ServerEntry};
+use crate::outbox::Outbox;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use url::Url;
+
+// How often servers are probed again while the server selection screen is open
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+// A saved server, as listed on the server selection screen
+#[derive(Clone)]
+pub struct Server {
+    pub name: String,
+    pub url: Url,
+}
+
+// Latest background check of a server, shown on the server selection screen
+pub enum ServerStatus {
+    Probing,
+    Reachable(Duration), // round trip of a ping
+    Unreachable(String),
+}
+
+pub struct Shared {
+    pub servers: Vec<Server>, // saved servers, in the order they are listed
+    pub config: Config,       // saved servers, usernames and preferences
+    pub outbox: Outbox,       // messages not yet acknowledged, for every server
+    server_status: HashMap<Url, (ServerStatus, Instant)>, // latest probe of each server URL, and when it started
+}
+
+impl Shared {
+    pub fn load() -> Shared {
+        let config = Config::load();
+        let outbox = Outbox::load(config.dir());
+        let mut servers: Vec<Server> = Vec::new();
+        for (name, url) in config.server_urls() {
+            if !servers.iter().any(|server| server.name == name) {
+                servers.push(Server { name, url });
+            }
+        }
+        Shared {
+            servers,
+            config,
+            outbox,
+            server_status: HashMap::new(),
+        }
+    }
+
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.servers.iter().position(|server| server.name == name)
+    }
+
+    // The server to select on launch: the one used last time, if it is still saved
+    pub fn initial_server(&self) -> Option<String> {
+        self.config
+            .last_server
+            .clone()
+            .filter(|name| self.position(name).is_some())
+            .or_else(|| self.servers.first().map(|server| server.name.clone()))
+    }
+
+    // Move a server `step` places up or down the list
+    pub fn move_server(&mut self, name: &str, step: isize) {
+        if let Some(index) = self.position(name) {
+            let target = index as isize + step;
+            if target >= 0 && (target as usize) < self.servers.len() {
+                self.servers.swap(index, target as usize);
+                self.save_config();
+            }
+        }
+    }
+
+    // Remove a server along with its remembered username and queued messages, returning the
+    // one that takes its place in the list
+    pub fn delete_server(&mut self, name: &str) -> Option<String> {
+        let index = self.position(name)?;
+        let removed = self.servers.remove(index);
+        self.config.usernames.remove(&removed.name);
+        self.outbox.remove_server(&removed.name);
+        self.save_config();
+        self.servers
+            .get(index.min(self.servers.len().saturating_sub(1)))
+            .map(|server| server.name.clone())
+    }
+
+    // Add the server typed as "name:url", or replace `editing` with it. Returns the saved
+    // name, or why the input was rejected.
+    pub fn save_server(&mut self, input: &str, editing: Option<&str>) -> Result<String, String> {
+        let (name, url) = parse_server_input(input)?;
+        if self
+            .servers
+            .iter()
+            .any(|server| server.name == name && Some(server.name.as_str()) != editing)
+        {
+            return Err(format!("There is already a server called {}.", name));
+        }
+
+        let server = Server {
+            name: name.clone(),
+            url,
+        };
+        match editing.and_then(|old| Some((self.position(old)?, old))) {
+            Some((index, old)) => {
+                // A renamed server keeps its remembered username and queued messages
+                if old != name {
+                    if let Some(username) = self.config.usernames.remove(old) {
+                        self.config.usernames.insert(name.clone(), username);
+                    }
+                    if self.config.last_server.as_deref() == Some(old) {
+                        self.config.last_server = Some(name.clone());
+                    }
+                    self.outbox.rename_server(old, &name);
+                }
+                self.servers[index] = server;
+            }
+            None => self.servers.push(server),
+        }
+        self.save_config();
+        Ok(name)
+    }
+
+    // Saved servers due to be probed: never checked, or last checked over `PROBE_INTERVAL`
+    // ago. They are marked as being probed until `set_server_status` records the result.
+    pub fn servers_to_probe(&mut self, now: Instant) -> Vec<Url> {
+        let mut due = Vec::new();
+        for server in &self.servers {
+            let stale = match self.server_status.get(&server.url) {
+                Some((ServerStatus::Probing, _)) => false,
+                Some((_, checked_at)) => now.duration_since(*checked_at) >= PROBE_INTERVAL,
+                None => true,
+            };
+            if stale && !due.contains(&server.url) {
+                due.push(server.url.clone());
+            }
+        }
+        for url in &due {
+            self.server_status
+                .insert(url.clone(), (ServerStatus::Probing, now));
+        }
+        due
+    }
+
+    pub fn set_server_status(&mut self, url: Url, result: Result<Duration, String>) {
+        let status = match result {
+            Ok(round_trip) => ServerStatus::Reachable(round_trip),
+            Err(e) => ServerStatus::Unreachable(e),
+        };
+        let checked_at = self
+            .server_status
+            .get(&url)
+            .map_or_else(Instant::now, |(_, checked_at)| *checked_at);
+        self.server_status.insert(url, (status, checked_at));
+    }
+
+    pub fn server_status(&self, url: &Url) -> Option<&ServerStatus> {
+        self.server_status.get(url).map(|(status, _)| status)
+    }
+
+    // Write the servers back to the config file, along with the usernames and preferences
+    // already recorded in it
+    pub fn save_config(&mut self) {
+        self.config.servers = self
+            .servers
+            .iter()
+            .map(|server| ServerEntry {
+                name: server.name.clone(),
+                url: server.url.to_string(),
+            })
+            .collect();
+        self.config.save();
+    }
+}
+
+// Split AddServer input of the form "name:url" and check that the URL is one we can connect to
+fn parse_server_input(input: &str) -> Result<(String, Url), String> {
+    let Some((name, url)) = input.split_once(':') else {
+        return Err("Enter the server as name:url, e.g. local:ws://127.0.0.1:8080".to_string());
+    };
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The server needs a name before the ':'.".to_string());
+    }
+    let url = Url::parse(url.trim()).map_err(|e| format!("Invalid URL: {}.", e))?;
+    if !matches!(url.scheme(), "ws" | "wss") {
+        return Err("The URL must start with ws:// or wss://.".to_string());
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err("The URL needs a host.".to_string());
+    }
+    Ok((name.to_string(), url))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_server_input() {
+        let (name, url) = parse_server_input(" home : ws://127.0.0.1:9000 ").unwrap();
+        assert_eq!(name, "home");
+        assert_eq!(url.as_str(), "ws://127.0.0.1:9000/");
+
+        let (name, url) = parse_server_input("remote:wss://chat.example.com/socket").unwrap();
+        assert_eq!(name, "remote");
+        assert_eq!(url.host_str(), Some("chat.example.com"));
+    }
+
+    #[test]
+    fn rejects_bad_server_input() {
+        assert!(parse_server_input("ws//127.0.0.1")
+            .unwrap_err()
+            .starts_with("Enter the server as name:url"));
+        assert!(parse_server_input(" :ws://127.0.0.1:9000")
+            .unwrap_err()
+            .contains("needs a name"));
+        assert!(parse_server_input("home:not a url")
+            .unwrap_err()
+            .starts_with("Invalid URL"));
+        assert!(parse_server_input("home:http://127.0.0.1:9000")
+            .unwrap_err()
+            .contains("ws:// or wss://"));
+        assert!(parse_server_input("home:ws://").is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//  This file contains the tabs, one per server connection. Each tab has its own `App` with
+//  the chat state for its server, the sending half of its connection and, once that drops,
+//  its background reconnect. What the connections receive and how the reconnects progress
+//  arrives on one channel, so the main loop can wait on every tab at once.
+use crate::app::{App, CurrentScreen};
+use crate::websocket::{Reconnect, ReconnectEvent, WsStream};
+use futures_util::stream::SplitSink;
+use futures_util::StreamExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, Message};
+
+// Identifies the connection or reconnect an event came from. Each gets a new ID, so events
+// still queued from one that has been replaced are recognised and dropped.
+pub type SourceId = u64;
+static NEXT_SOURCE_ID: AtomicU64 = AtomicU64::new(1);
+
+pub enum TabEvent {
+    // A frame from a tab's connection, or `None` once the connection has closed
+    Received(SourceId, Option<Result<Message, tungstenite::Error>>),
+    Reconnect(SourceId, ReconnectEvent),
+}
+
+// What the tab bar shows for a tab
+pub struct TabLabel {
+    pub name: String,
+    pub unread: usize,
+    pub online: bool,
+    pub active: bool,
+}
+
+pub struct Tab {
+    pub app: App,
+    pub write: Option<SplitSink<WsStream, Message>>,
+    reader: Option<(SourceId, JoinHandle<()>)>, // forwards the connection's frames
+    reconnect: Option<(SourceId, Reconnect)>,
+    events: mpsc::UnboundedSender<TabEvent>,
+}
+
+impl Tab {
+    fn new(app: App, events: mpsc::UnboundedSender<TabEvent>) -> Tab {
+        Tab {
+            app,
+            write: None,
+            reader: None,
+            reconnect: None,
+            events,
+        }
+    }
+
+    // Use a new connection: keep its sending half and forward what it receives to the
+    // main loop, replacing any current connection
+    pub fn attach(&mut self, ws_stream: WsStream) {
+        self.detach();
+        let (write, mut read) = ws_stream.split();
+        let id = NEXT_SOURCE_ID.fetch_add(1, Ordering::Relaxed);
+        let events = self.events.clone();
+        let reader = tokio::spawn(async move {
+            loop {
+                let message = read.next().await;
+                let closed = !matches!(message, Some(Ok(_)));
+                if events.send(TabEvent::Received(id, message)).is_err() || closed {
+                    break;
+                }
+            }
+        });
+        self.write = Some(write);
+        self.reader = Some((id, reader));
+    }
+
+    pub fn detach(&mut self) {
+        self.write = None;
+        if let Some((_, reader)) = self.reader.take() {
+            reader.abort();
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.write.is_some()
+    }
+
+    // Start reconnecting to this tab's server in the background, at its saved URL, replacing
+    // any reconnect already waiting
+    pub fn reconnect(&mut self, immediately: bool) {
+        let Some(url) = self.app.server_url() else {
+            return;
+        };
+        let id = NEXT_SOURCE_ID.fetch_add(1, Ordering::Relaxed);
+        let events = self.events.clone();
+        let reconnect = Reconnect::start(url, immediately, move |event| {
+            events.send(TabEvent::Reconnect(id, event)).is_ok()
+        });
+        self.reconnect = Some((id, reconnect));
+    }
+
+    pub fn is_reconnecting(&self) -> bool {
+        self.reconnect.is_some()
+    }
+
+    pub fn stop_reconnecting(&mut self) {
+        if self.reconnect.take().is_some() {
+            self.app.reconnect_attempt = 0;
+            self.app.reconnect_at = None;
+        }
+    }
+}
+
+impl Drop for Tab {
+    fn drop(&mut self) {
+        self.detach();
+    }
+}
+
+pub struct Tabs {
+    tabs: Vec<Tab>, // never empty
+    active: usize,  // the tab on screen
+    events: mpsc::UnboundedSender<TabEvent>,
+}
+
+impl Tabs {
+    // Start with a single tab for `app`, returning the channel the tabs' events arrive on
+    pub fn new(app: App) -> (Tabs, mpsc::UnboundedReceiver<TabEvent>) {
+        let (events, receiver) = mpsc::unbounded_channel();
+        let tabs = Tabs {
+            tabs: vec![Tab::new(app, events.clone())],
+            active: 0,
+            events,
+        };
+        (tabs, receiver)
+    }
+
+    pub fn active(&mut self) -> &mut Tab {
+        &mut self.tabs[self.active]
+    }
+
+    pub fn active_app(&mut self) -> &mut App {
+        &mut self.tabs[self.active].app
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Tab> {
+        self.tabs.iter_mut()
+    }
+
+    // The tab a connection or reconnect belongs to, unless it has since been replaced
+    pub fn by_source(&mut self, id: SourceId) -> Option<&mut Tab> {
+        self.tabs.iter_mut().find(|tab| {
+            tab.reader.as_ref().is_some_and(|(source, _)| *source == id)
+                || tab
+                    .reconnect
+                    .as_ref()
+                    .is_some_and(|(source, _)| *source == id)
+        })
+    }
+
+    // The tab connected, or reconnecting, to a server
+    pub fn find_server(&self, name: &str) -> Option<usize> {
+        self.tabs.iter().position(|tab| {
+            tab.app.server_name() == Some(name) && (tab.is_connected() || tab.is_reconnecting())
+        })
+    }
+
+    // Open a new tab next to the others and switch to it
+    pub fn open(&mut self) -> &mut Tab {
+        let app = self.active_app().new_tab();
+        self.tabs.push(Tab::new(app, self.events.clone()));
+        self.switch_to(self.tabs.len() - 1);
+        self.active()
+    }
+
+    pub fn switch_to(&mut self, index: usize) {
+        if index >= self.tabs.len() {
+            return;
+        }
+        self.active = index;
+        for (i, tab) in self.tabs.iter_mut().enumerate() {
+            tab.app.in_background = i != index;
+        }
+        self.tabs[index].app.unread = 0;
+    }
+
+    // Switch `step` tabs to the right, or to the left when negative, wrapping around
+    pub fn cycle(&mut self, step: isize) {
+        let index = (self.active as isize + step).rem_euclid(self.tabs.len() as isize);
+        self.switch_to(index as usize);
+    }
+
+    // Close the tab on screen and disconnect it. Closing the last one leaves a fresh tab on
+    // the server selection screen.
+    pub fn close_active(&mut self) {
+        if self.tabs.len() == 1 {
+            let mut app = self.active_app().new_tab();
+            app.current_screen = CurrentScreen::ServerSelection;
+            self.tabs[0] = Tab::new(app, self.events.clone());
+        } else {
+            self.tabs.remove(self.active);
+            self.switch_to(self.active.min(self.tabs.len() - 1));
+        }
+    }
+
+    // Follow an edit of a saved server in every tab connected to it. Reconnects already
+    // waiting start over, so they use the server's new URL.
+    pub fn server_edited(&mut self, old: &str, new_name: &str) {
+        for tab in &mut self.tabs {
+            tab.app.server_renamed(old, new_name);
+            if tab.app.server_name() == Some(new_name) && tab.is_reconnecting() {
+                tab.reconnect(false);
+            }
+        }
+    }
+
+    pub fn labels(&self) -> Vec<TabLabel> {
+        self.tabs
+            .iter()
+            .enumerate()
+            .map(|(i, tab)| TabLabel {
+                name: tab.app.server_name().unwrap_or("new").to_string(),
+                unread: tab.app.unread,
+                online: tab.app.online,
+                active: i == self.active,
+            })
+            .collect()
+    }
+}
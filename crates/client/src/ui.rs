@@ -1,4 +1,5 @@
-use crate::app::{App, CurrentScreen};
+use crate::app::CurrentScreen;
+use crate::tabs::Tabs;
 use ratatui::Frame;
 
 mod add_server;
@@ -11,15 +12,23 @@ mod server_selection;
 mod set_user;
 mod utils;
 
-pub fn ui(frame: &mut Frame, app: &mut App) {
+// Draw the tab on screen; the chat header also lists the other tabs
+pub fn ui(frame: &mut Frame, tabs: &mut Tabs) {
+    let labels = tabs.labels();
+    let app = tabs.active_app();
     match app.current_screen {
         CurrentScreen::LoggingIn => login::render_login(frame, app),
-        CurrentScreen::Main | CurrentScreen::ComposingMessage => chat::render_chat(frame, app),
+        CurrentScreen::Main | CurrentScreen::ComposingMessage | CurrentScreen::Thread => {
+            chat::render_chat(frame, app, &labels)
+        }
         CurrentScreen::HelpMenu => help::render_help(frame),
         CurrentScreen::Exiting | CurrentScreen::ExitingLoggingIn => exiting::render_exiting(frame),
-        CurrentScreen::Disconnected => disconnected::render_disconnected(frame),
+        CurrentScreen::Disconnected | CurrentScreen::Resuming => {
+            disconnected::render_disconnected(frame, app)
+        }
         CurrentScreen::SetUser => set_user::render_set_user(frame, app),
         CurrentScreen::ServerSelection => server_selection::render_server_selection(frame, app), // Route for the server selection screen
+        CurrentScreen::DeletingServer => server_selection::render_deleting_server(frame, app),
         CurrentScreen::AddServer => add_server::render_add_server(frame, app), // _ => {} // Handle other screens if needed
     }
 }
@@ -4,19 +4,31 @@ use crate::ui::utils::centered_rect;
 use ratatui::{
     layout::Position,
     style::{Color, Style},
+    text::{Line, Span},
     widgets::{Block, Borders, Clear, Paragraph, Wrap},
     Frame,
 }; // Import the utility functions
 
 pub fn render_add_server(frame: &mut Frame, app: &mut App) {
     frame.render_widget(Clear, frame.area());
+    let title = match &app.editing_server {
+        Some(name) => format!("Edit Server {} (name:url)", name),
+        None => "Add New Server (name:url)".to_string(),
+    };
     let block = Block::default()
-        .title("Add New Server (name:url)")
+        .title(title)
         .borders(Borders::ALL)
         .style(Style::default().bg(Color::DarkGray));
-    let paragraph = Paragraph::new(app.message_input.as_str())
-        .block(block)
-        .wrap(Wrap { trim: true });
+    // The input, with the reason it was rejected underneath
+    let mut lines = vec![Line::from(app.message_input.as_str())];
+    if let Some(error) = &app.server_error {
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            error.as_str(),
+            Style::default().fg(Color::Red),
+        )));
+    }
+    let paragraph = Paragraph::new(lines).block(block).wrap(Wrap { trim: true });
     let area = centered_rect(60, 25, frame.area());
     frame.render_widget(paragraph, area);
     let cursor_x = area.x + app.message_input.len() as u16 + 1;
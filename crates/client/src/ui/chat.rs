@@ -1,15 +1,18 @@
 // ui/chat.rs
-use crate::app::{App, CurrentScreen};
-use crate::ui::utils::{wrap_single_line, wrap_text};
+use crate::app::{App, CurrentScreen, QUICK_REACTIONS};
+use crate::tabs::TabLabel;
+use crate::ui::utils::{wrap_pending_messages, wrap_single_line, wrap_text};
+use std::collections::HashMap;
+
 use ratatui::{
     layout::{Constraint, Direction, Layout, Position},
-    style::{Color, Style},
+    style::{Color, Modifier, Style},
     text::{Line, Span},
     widgets::{Block, Borders, List, ListItem, Paragraph, Wrap},
     Frame,
 };
 
-pub fn render_chat(frame: &mut Frame, app: &mut App) {
+pub fn render_chat(frame: &mut Frame, app: &mut App, tabs: &[TabLabel]) {
     // Compose message scrolling management
     let input_lines = wrap_single_line(&app.message_input, frame.area().width as usize - 4); // Subtracting borders
 
@@ -26,43 +29,143 @@ pub fn render_chat(frame: &mut Frame, app: &mut App) {
         .cloned()
         .collect::<Vec<String>>();
 
+    // Who else is typing in the room on screen, shown on a status line when anyone is
+    let typing_status = typing_status(&app.typing_in_view());
+    let status_height = if typing_status.is_some() { 1 } else { 0 };
+
     // Layout based on dynamic input box height
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(3),                         // Title/Header
             Constraint::Min(1),                            // Messages List
+            Constraint::Length(status_height),             // Typing status
             Constraint::Length((input_height + 2) as u16), // Message Input Field
         ])
         .split(frame.area());
 
     // Header block (Title and Help)
-    let server_title = match &app.selected_server {
-        Some(server) => format!("TUI Messenger ({})", server),
-        None => "TUI Messenger".to_string(),
+    let server_title = match app.server_name() {
+        Some(server) if tabs.len() <= 1 => format!("TUI Messenger ({})", server),
+        _ => "TUI Messenger".to_string(),
+    };
+    let key_hint = if app.thread_parent.is_some() {
+        "(Esc) back"
+    } else {
+        "(h) help"
+    };
+    // While reconnecting the chat stays usable, with messages queued until the session is back
+    let status_label = if app.online {
+        app.status.label().unwrap_or_default()
+    } else {
+        "[offline]"
     };
-    const KEY_HINT: &str = "(h) help";
     let total_width = frame.area().width as usize;
 
+    // With several servers open, a numbered tab for each, showing how many messages arrived
+    // while it was in the background
+    let mut header_spans = vec![Span::styled(
+        server_title.clone(),
+        Style::default().fg(Color::Green),
+    )];
+    let mut rooms_width = 0;
+    for (i, tab) in tabs.iter().enumerate().filter(|_| tabs.len() > 1) {
+        let mut title = format!("{}:{}", i + 1, tab.name);
+        if tab.unread > 0 {
+            title.push_str(&format!(" ({})", tab.unread));
+        }
+        let style = match (tab.active, tab.online) {
+            (true, _) => Style::default().fg(Color::Black).bg(Color::Green),
+            (false, true) if tab.unread > 0 => Style::default().fg(Color::Yellow),
+            (false, true) => Style::default().fg(Color::Green),
+            (false, false) => Style::default().fg(Color::DarkGray),
+        };
+        rooms_width += title.chars().count() + 1;
+        header_spans.push(Span::raw(" "));
+        header_spans.push(Span::styled(title, style));
+    }
+    if tabs.len() > 1 {
+        header_spans.push(Span::raw(" |"));
+        rooms_width += 2;
+    }
+
+    // Joined rooms, with the active room highlighted, or the room of the open thread
+    if let Some(room) = app.thread_room() {
+        let title = format!("Thread in {}", room);
+        rooms_width += title.chars().count() + 1;
+        header_spans.push(Span::raw(" "));
+        header_spans.push(Span::styled(
+            title,
+            Style::default().fg(Color::Black).bg(Color::Cyan),
+        ));
+    }
+    for room in app.rooms.iter().filter(|_| app.thread_parent.is_none()) {
+        let style = if room == &app.current_room {
+            Style::default().fg(Color::Black).bg(Color::Cyan)
+        } else {
+            Style::default().fg(Color::Cyan)
+        };
+        header_spans.push(Span::raw(" "));
+        header_spans.push(Span::styled(room.clone(), style));
+        rooms_width += room.chars().count() + 1;
+    }
+
     // Ensure that we don't subtract too much and cause a crash
-    let space_padding = total_width.saturating_sub(server_title.len() + KEY_HINT.len() + 2); // Avoid negative values
-
-    let header = Paragraph::new(Line::from(vec![
-        Span::styled(server_title, Style::default().fg(Color::Green)),
-        Span::raw(" ".repeat(space_padding)), // Safely repeat spaces
-        Span::styled(KEY_HINT, Style::default().fg(Color::Red)),
-    ]))
-    .block(Block::default().borders(Borders::ALL));
+    let space_padding = total_width
+        .saturating_sub(server_title.len() + rooms_width + status_label.len() + key_hint.len() + 3); // Avoid negative values
+
+    header_spans.push(Span::raw(" ".repeat(space_padding))); // Safely repeat spaces
+    header_spans.push(Span::styled(
+        status_label,
+        Style::default().fg(Color::Yellow),
+    ));
+    header_spans.push(Span::raw(" "));
+    header_spans.push(Span::styled(key_hint, Style::default().fg(Color::Red)));
+    let header =
+        Paragraph::new(Line::from(header_spans)).block(Block::default().borders(Borders::ALL));
     frame.render_widget(header, chunks[0]);
 
     // Messages area with left/right alignment for sent/received messages
     let messages_area = chunks[1];
-    let max_width = messages_area.width.checked_sub(4).unwrap_or(0) as usize;
+    let max_width = messages_area.width.saturating_sub(4) as usize;
     let available_lines = (messages_area.height as usize).saturating_sub(2);
 
-    // Wrap messages, and calculate total lines
-    let wrapped_lines = wrap_text(&app.messages, max_width, app.username.as_deref());
+    // Wrap messages, and calculate total lines. Threads show every reply, so only the
+    // chat view summarizes them.
+    let reply_counts = if app.thread_parent.is_some() {
+        HashMap::new()
+    } else {
+        app.reply_counts()
+    };
+    let mut wrapped_lines = wrap_text(
+        &app.visible_messages(),
+        max_width,
+        app.username.as_deref(),
+        &app.timestamp_format,
+        app.selected_message,
+        &reply_counts,
+        app.first_unread(),
+    );
+    wrapped_lines.extend(wrap_pending_messages(
+        &app.room_pending_messages(),
+        max_width,
+    ));
     let total_lines = wrapped_lines.len();
+    app.max_scroll_offset = total_lines.saturating_sub(available_lines);
+
+    // Scroll just enough to keep the selected message in view. Its lines are the ones
+    // `wrap_text` highlighted.
+    let is_selected = |line: &Line| line.style.add_modifier.contains(Modifier::REVERSED);
+    let first_selected = wrapped_lines.iter().position(is_selected);
+    let last_selected = wrapped_lines.iter().rposition(is_selected);
+    if let (Some(first), Some(last)) = (first_selected, last_selected) {
+        let bottom_start = total_lines.saturating_sub(available_lines);
+        let mut start = bottom_start.saturating_sub(app.scroll_offset).min(first);
+        if last >= start + available_lines {
+            start = last + 1 - available_lines;
+        }
+        app.scroll_offset = bottom_start.saturating_sub(start);
+    }
 
     // Calculate starting line based on the scroll offset and total lines
     let start_line = total_lines
@@ -75,27 +178,62 @@ pub fn render_chat(frame: &mut Frame, app: &mut App) {
         .skip(start_line)
         .take(available_lines)
         .map(|line| {
-            ListItem::new(line) // The line is already a Span with styling
+            ListItem::new(line) // The line is already styled
         })
         .collect::<Vec<ListItem>>();
 
     let list = List::new(visible_lines).block(Block::default().borders(Borders::ALL));
     frame.render_widget(list, messages_area);
 
-    // Message input block
+    if let Some(status) = typing_status {
+        let status = Paragraph::new(Span::styled(
+            format!(" {}", status),
+            Style::default()
+                .fg(Color::DarkGray)
+                .add_modifier(Modifier::ITALIC),
+        ));
+        frame.render_widget(status, chunks[2]);
+    }
+
+    // Message input block, which shows the reaction keys while a message is selected
+    let compose_title = match (app.selected_message, app.thread_parent) {
+        (Some(id), None) => format!(
+            "Message #{} selected: (1-6) {} (Enter) thread (Esc) cancel",
+            id,
+            QUICK_REACTIONS.join(" ")
+        ),
+        (Some(id), Some(_)) => format!(
+            "Message #{} selected: (1-6) {} (Esc) cancel",
+            id,
+            QUICK_REACTIONS.join(" ")
+        ),
+        (None, Some(_)) => "Reply in Thread".to_string(),
+        (None, None) => "Compose Message".to_string(),
+    };
     let typing = Paragraph::new(visible_input_lines.join("\n"))
-        .block(
-            Block::default()
-                .borders(Borders::ALL)
-                .title("Compose Message"),
-        )
+        .block(Block::default().borders(Borders::ALL).title(compose_title))
         .wrap(Wrap { trim: true });
-    frame.render_widget(typing, chunks[2]);
+    frame.render_widget(typing, chunks[3]);
 
     // Set cursor position if composing a message
     if let CurrentScreen::ComposingMessage = app.current_screen {
-        let cursor_x = chunks[2].x + app.message_input.len() as u16 + 1;
-        let cursor_y = chunks[2].y + visible_input_lines.len() as u16;
+        let cursor_x = chunks[3].x + app.message_input.len() as u16 + 1;
+        let cursor_y = chunks[3].y + visible_input_lines.len() as u16;
         frame.set_cursor_position(Position::new(cursor_x, cursor_y));
     }
 }
+
+// "alice is typing…", "alice and bob are typing…", or a count once it gets crowded
+fn typing_status(usernames: &[&str]) -> Option<String> {
+    match usernames {
+        [] => None,
+        [one] => Some(format!("{} is typing…", one)),
+        [first, second] => Some(format!("{} and {} are typing…", first, second)),
+        [first, second, rest @ ..] => Some(format!(
+            "{}, {} and {} more are typing…",
+            first,
+            second,
+            rest.len()
+        )),
+    }
+}
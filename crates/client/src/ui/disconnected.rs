@@ -1,23 +1,53 @@
 // ui/disconnected.rs
+use crate::app::{App, CurrentScreen};
 use crate::ui::utils::centered_rect;
+use std::time::Instant;
+
 use ratatui::{
     style::{Color, Style},
     widgets::{Block, Borders, Clear, Paragraph, Wrap},
     Frame,
 };
 
-pub fn render_disconnected(frame: &mut Frame) {
+pub fn render_disconnected(frame: &mut Frame, app: &App) {
+    let (title, text) = match (&app.current_screen, app.reconnect_at) {
+        (CurrentScreen::Resuming, _) => (
+            "Reconnecting",
+            "Connected. Resuming your session...".to_string(),
+        ),
+        // Counting down to the next automatic attempt, or waiting for it to connect
+        (_, Some(retry_at)) => {
+            let status = match retry_at.checked_duration_since(Instant::now()) {
+                Some(wait) if !wait.is_zero() => format!(
+                    "Reconnecting in {}s (attempt {}).",
+                    wait.as_secs() + 1,
+                    app.reconnect_attempt
+                ),
+                _ => format!("Reconnecting (attempt {})...", app.reconnect_attempt),
+            };
+            (
+                "Disconnected",
+                format!(
+                    "Connection lost. {}\n\nPress 'c' to keep writing offline, 'r' to retry now, 's' to choose another server or 'q' to quit.",
+                    status
+                ),
+            )
+        }
+        _ => (
+            "Disconnected",
+            "Connection lost. Press 'r' to attempt to reconnect, 's' to choose another server or 'q' to quit."
+                .to_string(),
+        ),
+    };
     let block = Block::default()
-        .title("Disconnected")
+        .title(title)
         .borders(Borders::ALL)
         .style(Style::default().bg(Color::DarkGray));
-    let paragraph =
-        Paragraph::new("Connection lost. Press 'r' to attempt to reconnect or press 'q' to quit.")
-            .block(block)
-            .wrap(Wrap { trim: true })
-            .style(Style::default().fg(Color::Yellow));
+    let paragraph = Paragraph::new(text)
+        .block(block)
+        .wrap(Wrap { trim: true })
+        .style(Style::default().fg(Color::Yellow));
     let area = centered_rect(60, 25, frame.area());
     frame.render_widget(Clear, frame.area());
     frame.render_widget(paragraph, area);
-    return;
 }
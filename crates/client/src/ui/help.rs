@@ -16,12 +16,12 @@ pub fn render_help(frame: &mut Frame) {
         .borders(Borders::NONE)
         .style(Style::default().bg(Color::DarkGray));
     let help_menu_text = Text::styled(
-        "(q) to quit\n(n) to set username\n(s) to select server \n(↑↓) to scroll",
+        "(q) to quit\n(n) to set display name\n(s) to select server \n(Alt+1-9, Alt+←→) to switch tab, (Alt+w) to close it\n(↑↓) to scroll\n(Tab) to switch room\n(r) to select a message, (1-6) to react\n(Enter) on a selected message to open its thread\n\n/join #room, /leave [#room], /rooms\n/dm <user> <message>\n/edit <text>, /delete (your last message)\n/react [id] <emoji or :shortcode:>\n/notify all|mentions|off\n/away [reason], /dnd, /back",
         Style::default().fg(Color::Red),
     );
     let help_menu_paragraph = Paragraph::new(help_menu_text)
         .block(help_menu_block)
         .wrap(Wrap { trim: false });
-    let area = centered_rect(60, 25, frame.area());
+    let area = centered_rect(60, 50, frame.area());
     frame.render_widget(help_menu_paragraph, area);
 }
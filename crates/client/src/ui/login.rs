@@ -1,5 +1,6 @@
 // ui/login.rs
-use crate::app::{App, LoginField, MessageType};
+use crate::app::{App, LoginField, LoginMode};
+use protocol::MessageType;
 use ratatui::{
     layout::{Constraint, Direction, Layout, Position},
     widgets::{Block, Borders, Paragraph, Wrap},
@@ -9,18 +10,30 @@ use ratatui::{
 pub fn render_login(frame: &mut Frame, app: &mut App) {
     frame.render_widget(ratatui::widgets::Clear, frame.area());
 
+    let registering = matches!(app.login_mode, LoginMode::Register);
+
     // Layout for the login screen
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(3), // Title
-            Constraint::Length(3), // Username input
-            Constraint::Length(3), // Password input
-            Constraint::Length(2), // System message
-            Constraint::Min(0),    // Filler (remaining space)
+            Constraint::Length(3),                               // Title
+            Constraint::Length(3),                               // Username input
+            Constraint::Length(3),                               // Password input
+            Constraint::Length(if registering { 3 } else { 0 }), // Invite code input
+            Constraint::Length(3),                               // System message
+            Constraint::Min(0),                                  // Filler (remaining space)
         ])
         .split(frame.area());
 
+    // Title block showing the current mode and how to switch
+    let title = if registering {
+        "Create account  (c) back to sign in"
+    } else {
+        "Sign in  (c) create account"
+    };
+    let title_paragraph = Paragraph::new(title).block(Block::default().borders(Borders::ALL));
+    frame.render_widget(title_paragraph, chunks[0]);
+
     // Username input block
     let username_block = Block::default()
         .title("Username")
@@ -47,8 +60,8 @@ pub fn render_login(frame: &mut Frame, app: &mut App) {
             ratatui::style::Style::default()
         });
 
-    let password_input = Paragraph::new(if app.password.is_some() {
-        "*".repeat(app.password.as_ref().unwrap().len()) // Mask the password input
+    let password_input = Paragraph::new(if let Some(password) = &app.password {
+        "*".repeat(password.len()) // Mask the password input
     } else {
         String::new()
     })
@@ -57,6 +70,25 @@ pub fn render_login(frame: &mut Frame, app: &mut App) {
 
     frame.render_widget(password_input, chunks[2]);
 
+    // Invite code input block, only shown when creating an account
+    if registering {
+        let invite_block = Block::default()
+            .title("Invite code (optional)")
+            .borders(Borders::ALL)
+            .style(if let LoginField::InviteCode = app.current_login_field {
+                ratatui::style::Style::default().fg(ratatui::style::Color::Yellow)
+            // Highlight active input
+            } else {
+                ratatui::style::Style::default()
+            });
+
+        let invite_input = Paragraph::new(app.invite_code.clone().unwrap_or_default())
+            .block(invite_block)
+            .wrap(Wrap { trim: true });
+
+        frame.render_widget(invite_input, chunks[3]);
+    }
+
     // Display the most recent system message (e.g., authentication failure)
     let system_message = if let Some(last_message) = app.messages.last() {
         match last_message {
@@ -73,16 +105,18 @@ pub fn render_login(frame: &mut Frame, app: &mut App) {
     let message_paragraph = Paragraph::new(system_message)
         .block(message_block)
         .wrap(Wrap { trim: true });
-    frame.render_widget(message_paragraph, chunks[3]);
+    frame.render_widget(message_paragraph, chunks[4]);
 
     // Set cursor position based on the active field
     let cursor_x = match app.current_login_field {
         LoginField::Username => chunks[1].x + app.message_input.len() as u16 + 1,
         LoginField::Password => chunks[2].x + app.message_input.len() as u16 + 1,
+        LoginField::InviteCode => chunks[3].x + app.message_input.len() as u16 + 1,
     };
     let cursor_y = match app.current_login_field {
         LoginField::Username => chunks[1].y + 1,
         LoginField::Password => chunks[2].y + 1,
+        LoginField::InviteCode => chunks[3].y + 1,
     };
 
     frame.set_cursor_position(Position::new(cursor_x, cursor_y));
@@ -1,20 +1,25 @@
 // ui/server_selection.rs
 use crate::app::App;
+use crate::shared::ServerStatus;
+use crate::ui::utils::centered_rect;
 use ratatui::{
     layout::{Constraint, Direction, Layout},
     style::{Color, Style},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap},
     Frame,
 };
 
 pub fn render_server_selection(frame: &mut Frame, app: &mut App) {
     frame.render_widget(ratatui::widgets::Clear, frame.area());
 
+    let error_height = if app.server_error.is_some() { 3 } else { 0 };
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
-            Constraint::Length(3), // Title
-            Constraint::Min(1),    // Server list
+            Constraint::Length(3),            // Title
+            Constraint::Min(1),               // Server list
+            Constraint::Length(error_height), // Why connecting failed
         ])
         .split(frame.area());
 
@@ -22,24 +27,80 @@ pub fn render_server_selection(frame: &mut Frame, app: &mut App) {
     let title = Paragraph::new("Select a Server").block(Block::default().borders(Borders::ALL));
     frame.render_widget(title, chunks[0]);
 
-    // Render the server list
-    let server_list: Vec<ListItem> = app
+    // Render the server list in its saved order, each name followed by its URL and whether
+    // the latest probe reached it
+    let shared = app.shared();
+    let server_list: Vec<ListItem> = shared
         .servers
         .iter()
-        .map(|(name, _url)| {
-            let style = if Some(name) == app.selected_server.as_ref() {
+        .map(|server| {
+            let style = if Some(&server.name) == app.selected_server.as_ref() {
                 Style::default().fg(Color::Yellow)
             } else {
                 Style::default()
             };
-            ListItem::new(name.clone()).style(style)
+            ListItem::new(Line::from(vec![
+                Span::styled(server.name.clone(), style),
+                Span::styled(
+                    format!("  {}  ", server.url),
+                    Style::default().fg(Color::DarkGray),
+                ),
+                status_span(shared.server_status(&server.url)),
+            ]))
         })
         .collect();
 
     let server_list_widget = List::new(server_list).block(
         Block::default()
             .borders(Borders::ALL)
-            .title("Available Servers, add with (n)"),
+            .title("Available Servers: (n) add, (e) edit, (d) delete, (K/J) move up/down"),
     );
     frame.render_widget(server_list_widget, chunks[1]);
+
+    if let Some(error) = &app.server_error {
+        let error = Paragraph::new(error.as_str())
+            .style(Style::default().fg(Color::Red))
+            .block(Block::default().borders(Borders::ALL));
+        frame.render_widget(error, chunks[2]);
+    }
+}
+
+// Ask to confirm deleting the selected server, over the server list
+pub fn render_deleting_server(frame: &mut Frame, app: &mut App) {
+    render_server_selection(frame, app);
+
+    let name = app.selected_server.clone().unwrap_or_default();
+    let popup_block = Block::default()
+        .title("y/n")
+        .borders(Borders::NONE)
+        .style(Style::default().bg(Color::DarkGray));
+    let delete_text = Text::styled(
+        format!(
+            "Delete the server {}? Its saved username and any unsent messages for it are deleted too.",
+            name
+        ),
+        Style::default().fg(Color::Red),
+    );
+    let delete_paragraph = Paragraph::new(delete_text)
+        .block(popup_block)
+        .wrap(Wrap { trim: false });
+    let area = centered_rect(60, 25, frame.area());
+    frame.render_widget(Clear, area);
+    frame.render_widget(delete_paragraph, area);
+}
+
+fn status_span(status: Option<&ServerStatus>) -> Span<'static> {
+    match status {
+        Some(ServerStatus::Reachable(round_trip)) => Span::styled(
+            format!("● {} ms", round_trip.as_millis()),
+            Style::default().fg(Color::Green),
+        ),
+        Some(ServerStatus::Unreachable(reason)) => Span::styled(
+            format!("● unreachable: {}", reason),
+            Style::default().fg(Color::Red),
+        ),
+        Some(ServerStatus::Probing) | None => {
+            Span::styled("○ checking…", Style::default().fg(Color::DarkGray))
+        }
+    }
 }
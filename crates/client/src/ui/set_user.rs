@@ -11,7 +11,7 @@ use ratatui::{
 pub fn render_set_user(frame: &mut Frame, app: &mut App) {
     frame.render_widget(Clear, frame.area());
     let block = Block::default()
-        .title("Set Username")
+        .title("Set Display Name")
         .borders(Borders::ALL)
         .style(Style::default().bg(Color::DarkGray));
     let paragraph = Paragraph::new(app.message_input.as_str())
@@ -1,10 +1,13 @@
 // Define `centered_rect`
-use crate::app::MessageType;
+use crate::app::{DeliveryState, PendingMessage};
+use chrono::{DateTime, Local, Utc};
+use protocol::{find_mentions, MessageId, MessageType, Reactions};
+use std::collections::HashMap;
 
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Style},
-    text::Span,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
 };
 
 pub fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -32,45 +35,300 @@ pub fn wrap_text(
     messages: &[MessageType],
     max_width: usize,
     current_username: Option<&str>,
-) -> Vec<Span<'static>> {
+    timestamp_format: &str,
+    selected: Option<MessageId>,
+    reply_counts: &HashMap<MessageId, usize>,
+    first_unread: Option<MessageId>,
+) -> Vec<Line<'static>> {
     let mut lines = Vec::new();
 
     for message in messages {
+        if matches!(message, MessageType::ChatMessage { id, .. } if Some(*id) == first_unread) {
+            lines.push(new_messages_divider(max_width));
+        }
+        let first_line = lines.len();
         match message {
-            MessageType::ChatMessage { sender, content } => {
-                let wrapped_lines = wrap_single_line(content, max_width);
-                if Some(sender.as_str()) == current_username {
-                    // Right-align the current user's messages with Cyan color
-                    for line in wrapped_lines {
-                        let padding = " ".repeat(max_width.saturating_sub(line.len()));
-                        lines.push(Span::styled(
-                            format!("{}{}", padding, line),
+            MessageType::ChatMessage {
+                id,
+                timestamp,
+                sender,
+                deleted: true,
+                ..
+            } => {
+                // Deleted messages leave a tombstone in their place, still leading to
+                // their thread
+                let time = format_timestamp(timestamp, timestamp_format);
+                let style = Style::default().fg(Color::DarkGray);
+                let outgoing = Some(sender.as_str()) == current_username;
+                if outgoing {
+                    let text = format!("{}message deleted", time_column(&time, 0));
+                    lines.push(right_aligned(text, max_width, style));
+                } else {
+                    let text = format!("{}{}: message deleted", time_column(&time, 0), sender);
+                    lines.push(Span::styled(text, style).into());
+                }
+                if let Some(count) = reply_counts.get(id) {
+                    lines.push(thread_summary(*count, &time, outgoing, max_width));
+                }
+            }
+            MessageType::ChatMessage {
+                timestamp,
+                sender,
+                content,
+                recipient: Some(recipient),
+                mentions,
+                edited_at,
+                reactions,
+                ..
+            } => {
+                let mention = mentioned(current_username, mentions);
+                // Direct messages are shown in magenta with who they are from and to
+                let outgoing = Some(sender.as_str()) == current_username;
+                let label = if outgoing {
+                    format!("[DM to {}]", recipient)
+                } else {
+                    format!("[DM from {}]", sender)
+                };
+                let time = format_timestamp(timestamp, timestamp_format);
+                let wrapped_lines = wrap_single_line(
+                    &with_edited_marker(content, edited_at.is_some()),
+                    max_width.saturating_sub(time.len() + label.len() + 2),
+                );
+                for (i, line) in wrapped_lines.into_iter().enumerate() {
+                    let text = format!("{}{} {}", time_column(&time, i), label, line);
+                    let padding = if outgoing {
+                        " ".repeat(max_width.saturating_sub(text.chars().count()))
+                    } else {
+                        String::new()
+                    };
+                    lines.push(highlight_mentions(
+                        format!("{}{}", padding, text),
+                        Style::default().fg(Color::Magenta),
+                        mention,
+                    ));
+                }
+                if !reactions.is_empty() {
+                    let summary = reaction_summary(reactions);
+                    let style = Style::default().fg(Color::DarkGray);
+                    lines.push(annotation_line(summary, &time, outgoing, max_width, style));
+                }
+            }
+            MessageType::ChatMessage {
+                id,
+                timestamp,
+                sender,
+                content,
+                mentions,
+                edited_at,
+                reactions,
+                ..
+            } => {
+                let mention = mentioned(current_username, mentions);
+                let time = format_timestamp(timestamp, timestamp_format);
+                let wrapped_lines = wrap_single_line(
+                    &with_edited_marker(content, edited_at.is_some()),
+                    max_width.saturating_sub(time.len() + 3),
+                );
+                let outgoing = Some(sender.as_str()) == current_username;
+                if outgoing {
+                    // Right-align the current user's messages with Cyan color, marking the
+                    // last line as delivered
+                    let last = wrapped_lines.len().saturating_sub(1);
+                    for (i, line) in wrapped_lines.into_iter().enumerate() {
+                        let marker = if i == last { " ✓" } else { "  " };
+                        let text = format!("{}{}{}", time_column(&time, i), line, marker);
+                        let padding = " ".repeat(max_width.saturating_sub(text.chars().count()));
+                        lines.push(highlight_mentions(
+                            format!("{}{}", padding, text),
                             Style::default().fg(Color::Cyan),
+                            mention,
                         ));
                     }
                 } else {
                     // Left-align other users' messages with Green color
-                    for line in wrapped_lines {
-                        lines.push(Span::styled(
-                            format!("{}: {}", sender, line),
+                    for (i, line) in wrapped_lines.into_iter().enumerate() {
+                        lines.push(highlight_mentions(
+                            format!("{}{}: {}", time_column(&time, i), sender, line),
                             Style::default().fg(Color::Green),
+                            mention,
                         ));
                     }
                 }
+                if !reactions.is_empty() {
+                    let summary = reaction_summary(reactions);
+                    let style = Style::default().fg(Color::DarkGray);
+                    lines.push(annotation_line(summary, &time, outgoing, max_width, style));
+                }
+                if let Some(count) = reply_counts.get(id) {
+                    lines.push(thread_summary(*count, &time, outgoing, max_width));
+                }
             }
             MessageType::SystemMessage(system_message) => {
                 let wrapped_lines = wrap_single_line(system_message, max_width);
                 for line in wrapped_lines {
-                    lines.push(Span::styled(line, Style::default().fg(Color::Yellow)));
+                    lines.push(Span::styled(line, Style::default().fg(Color::Yellow)).into());
                 }
             }
             _ => {}
         }
+
+        // Highlight every line of the message selected for reactions
+        if matches!(message, MessageType::ChatMessage { id, .. } if Some(*id) == selected) {
+            for line in &mut lines[first_line..] {
+                line.style = line.style.add_modifier(Modifier::REVERSED);
+            }
+        }
+    }
+
+    lines
+}
+
+// Reaction counts shown under a message, e.g. "👍 2  🎉 1"
+fn reaction_summary(reactions: &Reactions) -> String {
+    reactions
+        .iter()
+        .map(|(emoji, users)| format!("{} {}", emoji, users.len()))
+        .collect::<Vec<String>>()
+        .join("  ")
+}
+
+// "─── new messages ───" across the full width, above the first unread message
+fn new_messages_divider(max_width: usize) -> Line<'static> {
+    let label = " new messages ";
+    let rule = max_width.saturating_sub(label.len());
+    let text = format!(
+        "{}{}{}",
+        "─".repeat(rule / 2),
+        label,
+        "─".repeat(rule - rule / 2)
+    );
+    Span::styled(text, Style::default().fg(Color::Red)).into()
+}
+
+// Compact thread summary under a message; the replies themselves are shown in the thread
+fn thread_summary(count: usize, time: &str, outgoing: bool, max_width: usize) -> Line<'static> {
+    let summary = match count {
+        1 => "↳ 1 reply".to_string(),
+        n => format!("↳ {} replies", n),
+    };
+    let style = Style::default().fg(Color::Blue);
+    annotation_line(summary, time, outgoing, max_width, style)
+}
+
+// A line under a message (reactions, thread summary) on the same side as the message
+fn annotation_line(
+    text: String,
+    time: &str,
+    outgoing: bool,
+    max_width: usize,
+    style: Style,
+) -> Line<'static> {
+    if outgoing {
+        right_aligned(text, max_width, style)
+    } else {
+        Span::styled(format!("{}{}", time_column(time, 1), text), style).into()
+    }
+}
+
+// The current user's name, if the server flagged the message as mentioning them
+fn mentioned<'a>(current_username: Option<&'a str>, mentions: &[String]) -> Option<&'a str> {
+    current_username.filter(|username| mentions.iter().any(|mention| mention == username))
+}
+
+// A line of message text with the mentions of `username` highlighted
+fn highlight_mentions(text: String, style: Style, username: Option<&str>) -> Line<'static> {
+    let Some(username) = username else {
+        return Span::styled(text, style).into();
+    };
+    let highlight = style
+        .fg(Color::Black)
+        .bg(Color::Yellow)
+        .add_modifier(Modifier::BOLD);
+    let mut spans = Vec::new();
+    let mut plain_start = 0;
+    for (start, name) in find_mentions(&text) {
+        if name != username {
+            continue;
+        }
+        let end = start + 1 + name.len();
+        spans.push(Span::styled(text[plain_start..start].to_string(), style));
+        spans.push(Span::styled(text[start..end].to_string(), highlight));
+        plain_start = end;
+    }
+    spans.push(Span::styled(text[plain_start..].to_string(), style));
+    Line::from(spans)
+}
+
+fn with_edited_marker(content: &str, edited: bool) -> String {
+    if edited {
+        format!("{} (edited)", content)
+    } else {
+        content.to_string()
+    }
+}
+
+// Format a message time in the local timezone
+fn format_timestamp(timestamp: &DateTime<Utc>, format: &str) -> String {
+    timestamp.with_timezone(&Local).format(format).to_string()
+}
+
+// The time is shown on the first line of a message; continuation lines are indented to match.
+// An empty format hides times altogether.
+fn time_column(time: &str, line_index: usize) -> String {
+    if time.is_empty() {
+        String::new()
+    } else if line_index == 0 {
+        format!("{} ", time)
+    } else {
+        " ".repeat(time.chars().count() + 1)
+    }
+}
+
+// Messages the server has not acknowledged yet: right-aligned like the user's own messages,
+// greyed out while pending or queued offline and red with the reason once they have failed
+pub fn wrap_pending_messages(pending: &[&PendingMessage], max_width: usize) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+
+    for message in pending {
+        let (marker, style) = match &message.state {
+            DeliveryState::Pending => (" …".to_string(), Style::default().fg(Color::DarkGray)),
+            DeliveryState::Queued => (
+                " ⏸ queued".to_string(),
+                Style::default().fg(Color::DarkGray),
+            ),
+            DeliveryState::Failed(reason) => {
+                (format!(" ✗ {}", reason), Style::default().fg(Color::Red))
+            }
+        };
+        let wrapped_lines = wrap_single_line(&message.content, max_width.saturating_sub(2));
+        let last = wrapped_lines.len().saturating_sub(1);
+        for (i, line) in wrapped_lines.into_iter().enumerate() {
+            if i == last {
+                // The delivery state goes on a line of its own if it does not fit
+                if line.chars().count() + marker.chars().count() > max_width {
+                    lines.push(right_aligned(line, max_width, style));
+                    lines.push(right_aligned(marker.trim().to_string(), max_width, style));
+                } else {
+                    lines.push(right_aligned(
+                        format!("{}{}", line, marker),
+                        max_width,
+                        style,
+                    ));
+                }
+            } else {
+                lines.push(right_aligned(line, max_width, style));
+            }
+        }
     }
 
     lines
 }
 
+fn right_aligned(text: String, max_width: usize, style: Style) -> Line<'static> {
+    let padding = " ".repeat(max_width.saturating_sub(text.chars().count()));
+    Span::styled(format!("{}{}", padding, text), style).into()
+}
+
 pub fn wrap_single_line(line: &str, max_width: usize) -> Vec<String> {
     let max_width = std::cmp::max(max_width, 10); // Avoid subtracting below a reasonable minimum width
     let mut wrapped_lines = Vec::new();
@@ -1,82 +1,213 @@
 use crate::app::App;
 use futures_util::{SinkExt, StreamExt};
-use ratatui::backend::Backend;
-use ratatui::Terminal;
+use rand::Rng;
+use std::time::{Duration, Instant};
 use tokio::io;
 use tokio::net::TcpStream;
-use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use url::Url;
 
 pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
+// Delay before the first automatic reconnect attempt; it doubles after every failure up
+// to `RECONNECT_MAX_DELAY`
+const RECONNECT_BASE_DELAY: Duration = Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+// Give up on a single connection attempt after this long
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Servers that have not answered a probe within this long are shown as unreachable
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub async fn connect_to_server(
     app: &App,
 ) -> Result<WsStream, Box<dyn std::error::Error + Send + Sync>> {
-    if let Some(server_name) = &app.selected_server {
-        if let Some(server_url) = app.servers.get(server_name) {
-            let url_string = server_url.to_string();
-            let (ws_stream, _) = connect_async(&url_string).await?;
-            return Ok(ws_stream);
+    match app.selected_server_url() {
+        Some(url) => connect_to_url(&url).await,
+        None => Err(Box::new(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No server selected",
+        ))),
+    }
+}
+
+async fn connect_to_url(url: &Url) -> Result<WsStream, Box<dyn std::error::Error + Send + Sync>> {
+    let (ws_stream, _) = tokio::time::timeout(CONNECT_TIMEOUT, connect_async(url.as_str()))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connection timed out"))??;
+    Ok(ws_stream)
+}
+
+// Check that a server accepts connections and time the round trip of a ping, in the
+// background. The result is sent to the main loop along with the server's URL.
+pub fn probe_server(url: Url, results: mpsc::UnboundedSender<(Url, Result<Duration, String>)>) {
+    tokio::spawn(async move {
+        let result = tokio::time::timeout(PROBE_TIMEOUT, ping(&url))
+            .await
+            .unwrap_or_else(|_| Err("timed out".to_string()));
+        let _ = results.send((url, result));
+    });
+}
+
+async fn ping(url: &Url) -> Result<Duration, String> {
+    let (mut ws_stream, _) = connect_async(url.as_str())
+        .await
+        .map_err(|e| e.to_string())?;
+    let sent_at = Instant::now();
+    ws_stream
+        .send(Message::Ping(Vec::new()))
+        .await
+        .map_err(|e| e.to_string())?;
+    while let Some(message) = ws_stream.next().await {
+        if let Message::Pong(_) = message.map_err(|e| e.to_string())? {
+            let round_trip = sent_at.elapsed();
+            let _ = ws_stream.close(None).await;
+            return Ok(round_trip);
         }
     }
-    Err(Box::new(io::Error::new(
-        io::ErrorKind::NotFound,
-        "No server selected",
-    )))
+    Err("connection closed".to_string())
 }
 
-pub async fn handle_websocket<B: Backend>(
-    app: &mut App,
-    terminal: &mut Terminal<B>,
-    write: &mut futures_util::stream::SplitSink<WsStream, Message>,
-    read: &mut futures_util::stream::SplitStream<WsStream>,
-) -> io::Result<()> {
-    loop {
-        tokio::select! {
-            ws_msg = read.next() => {
-                match ws_msg {
-                    Some(Ok(Message::Text(text))) => {
-                        app.handle_websocket_message(&text);
-                        terminal.draw(|f| crate::ui::ui(f, app))
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-                    }
-                    Some(Ok(Message::Binary(_))) => {
-                        // Handle binary message if needed
-                    }
-                    Some(Ok(Message::Ping(ping))) => {
-                        // Respond to ping by sending a Pong message
-                      write.send(Message::Pong(ping)).await.map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-                    }
-                    Some(Ok(Message::Pong(_))) => {
-                        // Handle pong if necessary
-                    }
-                    Some(Ok(Message::Close(_))) => {
-                        app.current_screen = crate::app::CurrentScreen::Disconnected;
-                        terminal.draw(|f| crate::ui::ui(f, app))
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-                        break;
-                    }
-                    Some(Err(e)) => {
-                        // Log the WebSocket error and move to the Disconnected state
-                        app.current_screen = crate::app::CurrentScreen::Disconnected;
-                        terminal.draw(|f| crate::ui::ui(f, app))
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-                        log::error!("WebSocket error: {:?}", e);
-                        break;
-                    }
-                    None => {
-                        // Handle the case when the stream ends
-                        app.current_screen = crate::app::CurrentScreen::Disconnected;
-                        terminal.draw(|f| crate::ui::ui(f, app))
-                            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-                        break;
-                    }
-                    Some(Ok(Message::Frame(frame_data))) => {
-                        let _ = frame_data;
+// Progress of a background reconnect, reported to the main loop
+pub enum ReconnectEvent {
+    Waiting { attempt: u32, retry_at: Instant },
+    Connected(Box<WsStream>),
+}
+
+// A background task that keeps trying to reach a server until it succeeds. Dropping the
+// handle cancels it, e.g. when the user switches to another server.
+pub struct Reconnect {
+    task: JoinHandle<()>,
+}
+
+impl Reconnect {
+    // Start reconnecting to `url`, making the first attempt right away or after a short,
+    // randomized wait. Progress is passed to `report`, which returns false once nobody is
+    // listening any more.
+    pub fn start(
+        url: Url,
+        immediately: bool,
+        report: impl Fn(ReconnectEvent) -> bool + Send + 'static,
+    ) -> Reconnect {
+        let task = tokio::spawn(async move {
+            let mut delay = if immediately {
+                Duration::ZERO
+            } else {
+                backoff_delay(0)
+            };
+            for attempt in 1.. {
+                let retry_at = Instant::now() + delay;
+                if !report(ReconnectEvent::Waiting { attempt, retry_at }) {
+                    return;
+                }
+                tokio::time::sleep(delay).await;
+
+                match connect_to_url(&url).await {
+                    Ok(ws_stream) => {
+                        report(ReconnectEvent::Connected(Box::new(ws_stream)));
+                        return;
                     }
+                    Err(e) => log::warn!("Reconnect attempt {} failed: {}", attempt, e),
                 }
+                delay = backoff_delay(attempt);
             }
-        }
+        });
+        Reconnect { task }
     }
+}
 
+impl Drop for Reconnect {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Wait after `failures` failed attempts: exponential, capped, with half of it randomized so
+// clients dropped at the same moment do not all retry together
+fn backoff_delay(failures: u32) -> Duration {
+    let exponential = RECONNECT_BASE_DELAY.saturating_mul(1 << failures.min(16));
+    let capped = exponential.min(RECONNECT_MAX_DELAY);
+    capped / 2 + capped.mul_f64(rand::thread_rng().gen_range(0.0..0.5))
+}
+
+// Send any requests the App queued while handling input (e.g. history backfill). They wait
+// while the client is offline, until the session is authenticated again.
+pub async fn flush_outgoing(
+    app: &mut App,
+    write: &mut futures_util::stream::SplitSink<WsStream, Message>,
+) -> io::Result<()> {
+    if !app.online {
+        return Ok(());
+    }
+    while let Some(message) = app.outgoing.pop_front() {
+        write
+            .send(Message::Text(message.to_json()))
+            .await
+            .map_err(io::Error::other)?;
+    }
     Ok(())
 }
+
+// Handle one frame from the server, or `None` once the connection has closed. Returns
+// false when the connection is gone, after moving the App to the Disconnected screen.
+pub async fn handle_websocket(
+    app: &mut App,
+    write: &mut futures_util::stream::SplitSink<WsStream, Message>,
+    message: Option<Result<Message, tungstenite::Error>>,
+) -> io::Result<bool> {
+    match message {
+        Some(Ok(Message::Text(text))) => {
+            app.handle_websocket_message(&text);
+            app.mark_read();
+            flush_outgoing(app, write).await?;
+        }
+        Some(Ok(Message::Ping(ping))) => {
+            // Respond to ping by sending a Pong message
+            write
+                .send(Message::Pong(ping))
+                .await
+                .map_err(io::Error::other)?;
+        }
+        // Binary, pong and raw frames are not used
+        Some(Ok(Message::Binary(_) | Message::Pong(_) | Message::Frame(_))) => {}
+        Some(Ok(Message::Close(_))) | None => {
+            app.requeue_pending_messages();
+            app.current_screen = crate::app::CurrentScreen::Disconnected;
+            return Ok(false);
+        }
+        Some(Err(e)) => {
+            // Log the WebSocket error and move to the Disconnected state
+            log::error!("WebSocket error: {:?}", e);
+            app.requeue_pending_messages();
+            app.current_screen = crate::app::CurrentScreen::Disconnected;
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_exponentially() {
+        for failures in 0..5 {
+            let full = RECONNECT_BASE_DELAY * (1 << failures);
+            let delay = backoff_delay(failures);
+            assert!(delay >= full / 2, "{failures}: {delay:?}");
+            assert!(delay < full, "{failures}: {delay:?}");
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        for failures in [6, 10, 16, 32, u32::MAX] {
+            let delay = backoff_delay(failures);
+            assert!(delay >= RECONNECT_MAX_DELAY / 2, "{failures}: {delay:?}");
+            assert!(delay < RECONNECT_MAX_DELAY, "{failures}: {delay:?}");
+        }
+    }
+}
@@ -0,0 +1,478 @@
+//  This crate contains the wire format shared by the client and server binaries.
+//  Every message sent over the WebSocket is a `MessageType` serialized as JSON text,
+//  so both sides must agree on these definitions.
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+mod mention;
+mod reaction;
+mod room;
+
+pub use mention::find_mentions;
+pub use reaction::{normalize_reaction, Reactions};
+pub use room::{
+    direct_room, direct_room_participants, normalize_room_name, RoomSummary, DEFAULT_ROOM,
+};
+
+// Server-assigned message identifier; IDs increase monotonically
+pub type MessageId = u64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum MessageType {
+    // A stored message in `room`, with the ID and UTC timestamp assigned by the server.
+    // Direct messages also name their `recipient` and live in the pair's `direct_room`;
+    // they are sent with the `DirectMessage` command. Replies in a thread name the
+    // message that started it in `reply_to`, and `mentions` lists the users mentioned in
+    // the content with `@name`. Deleted messages are kept as tombstones with
+    // empty content so replies and history stay in place.
+    ChatMessage {
+        id: MessageId,
+        timestamp: DateTime<Utc>,
+        sender: String,
+        content: String,
+        room: String,
+        recipient: Option<String>,
+        reply_to: Option<MessageId>,
+        mentions: Vec<String>,
+        edited_at: Option<DateTime<Utc>>,
+        deleted: bool,
+        reactions: Reactions,
+    },
+    // Post a message to a room. `nonce` is chosen by the client to match the reply: the
+    // server answers with `Ack` once the message is stored, then broadcasts the resulting
+    // `ChatMessage` to every member of the room including the sender, or answers with
+    // `SendFailed` if the message was rejected. Setting `reply_to` posts the message as a
+    // reply in that message's thread.
+    SendMessage {
+        room: String,
+        content: String,
+        nonce: String,
+        reply_to: Option<MessageId>,
+    },
+    Ack {
+        nonce: String,
+        id: MessageId,
+        timestamp: DateTime<Utc>,
+    },
+    SendFailed {
+        nonce: String,
+        reason: String,
+    },
+    // Change or retract a stored message; allowed for its author and for moderators.
+    // The server broadcasts `MessageEdited`/`MessageDeleted` to everyone who can see it.
+    EditMessage {
+        id: MessageId,
+        content: String,
+    },
+    DeleteMessage {
+        id: MessageId,
+    },
+    MessageEdited {
+        id: MessageId,
+        room: String,
+        content: String,
+        mentions: Vec<String>,
+        edited_at: DateTime<Utc>,
+    },
+    MessageDeleted {
+        id: MessageId,
+        room: String,
+    },
+    // Add or remove the sender's reaction to a message; `emoji` is normalized with
+    // `normalize_reaction`. Every change is broadcast as a `ReactionChanged` delta.
+    AddReaction {
+        id: MessageId,
+        emoji: String,
+    },
+    RemoveReaction {
+        id: MessageId,
+        emoji: String,
+    },
+    ReactionChanged {
+        id: MessageId,
+        room: String,
+        emoji: String,
+        username: String,
+        added: bool,
+    },
+    // Sent every few seconds while the user is composing a message for `room`. The server
+    // passes it on to the room's other members as `UserTyping`, whose indicator should
+    // disappear after `expires_in_ms` unless renewed, or once the user's message arrives.
+    Typing {
+        room: String,
+    },
+    UserTyping {
+        room: String,
+        username: String,
+        expires_in_ms: u64,
+    },
+    // Read receipts: the client reports the newest message it has shown at the bottom of
+    // `room`, and after logging in the server sends the stored position for each room with
+    // one, ahead of that room's history
+    MarkRead {
+        room: String,
+        id: MessageId,
+    },
+    ReadPosition {
+        room: String,
+        last_read: MessageId,
+    },
+    Command {
+        name: String,
+        args: Vec<String>,
+    },
+    SystemMessage(String),
+    // Authentication handshake: the client sends `Login` and the server answers
+    // with `LoginOk` or `LoginFailed`, closing the connection once no attempts remain.
+    // `LoginOk` names the account and carries a session token for resuming later.
+    Login {
+        username: String,
+        password: String,
+    },
+    LoginOk {
+        username: String,
+        token: String,
+    },
+    LoginFailed {
+        remaining_attempts: u8,
+    },
+    // Reconnecting with the token from `LoginOk` instead of a password. The server answers
+    // with `LoginOk`, then sends each room's read position and a `History` page reaching
+    // back to `last_seen_id`, or with `ResumeFailed` when the token has expired and the user
+    // must log in again. A failed resume counts against the connection's login attempts.
+    Resume {
+        token: String,
+        last_seen_id: Option<MessageId>,
+    },
+    ResumeFailed,
+    // Account creation, only honoured when the server has registration enabled.
+    // A successful registration is followed by `LoginOk` for the new account.
+    Register {
+        username: String,
+        password: String,
+        invite_code: Option<String>,
+    },
+    RegisterOk,
+    RegisterFailed {
+        reason: String,
+    },
+    // History paging within a room: ask for up to `limit` messages older than `before_id`
+    // (or the newest page when `None`). The server answers with `History`, oldest message
+    // first, where `oldest_id` is the cursor for the next request. Rooms the user cannot
+    // read get an empty page without more to load.
+    FetchHistory {
+        room: String,
+        before_id: Option<MessageId>,
+        limit: u32,
+    },
+    History {
+        room: String,
+        messages: Vec<MessageType>,
+        oldest_id: Option<MessageId>,
+        has_more: bool,
+    },
+    // Room membership changes for this client, sent in reply to the `join`/`leave`
+    // commands and for every room the user is in after logging in
+    RoomJoined {
+        room: String,
+    },
+    RoomLeft {
+        room: String,
+    },
+    // Reply to the `rooms` command
+    RoomList {
+        rooms: Vec<RoomSummary>,
+    },
+}
+
+impl MessageType {
+    // Serialize the message into the JSON text sent over the socket
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("MessageType is always serializable")
+    }
+
+    // Parse a JSON text frame received from the socket
+    pub fn from_json(text: &str) -> serde_json::Result<MessageType> {
+        serde_json::from_str(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timestamp() -> DateTime<Utc> {
+        "2024-10-01T12:30:00Z".parse().unwrap()
+    }
+
+    fn round_trip(message: MessageType) {
+        let json = message.to_json();
+        let decoded = MessageType::from_json(&json).expect("failed to decode");
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn chat_message_round_trip() {
+        round_trip(MessageType::SendMessage {
+            room: DEFAULT_ROOM.to_string(),
+            content: "hello: world".to_string(),
+            nonce: "n1".to_string(),
+            reply_to: None,
+        });
+        round_trip(MessageType::SendMessage {
+            room: DEFAULT_ROOM.to_string(),
+            content: "in a thread".to_string(),
+            nonce: "n3".to_string(),
+            reply_to: Some(1),
+        });
+        round_trip(MessageType::Ack {
+            nonce: "n1".to_string(),
+            id: 1,
+            timestamp: timestamp(),
+        });
+        round_trip(MessageType::SendFailed {
+            nonce: "n2".to_string(),
+            reason: "You are not in #ops.".to_string(),
+        });
+        round_trip(MessageType::ChatMessage {
+            id: 1,
+            timestamp: timestamp(),
+            sender: "user1".to_string(),
+            content: "hello: world".to_string(),
+            room: DEFAULT_ROOM.to_string(),
+            recipient: None,
+            reply_to: None,
+            mentions: vec![],
+            edited_at: None,
+            deleted: false,
+            reactions: Reactions::new(),
+        });
+        round_trip(MessageType::ChatMessage {
+            id: 2,
+            timestamp: timestamp(),
+            sender: "user1".to_string(),
+            content: "just between us".to_string(),
+            room: direct_room("user1", "user2"),
+            recipient: Some("user2".to_string()),
+            reply_to: Some(1),
+            mentions: vec![],
+            edited_at: Some(timestamp()),
+            deleted: false,
+            reactions: Reactions::new(),
+        });
+    }
+
+    #[test]
+    fn command_round_trip() {
+        round_trip(MessageType::Command {
+            name: "list".to_string(),
+            args: vec![],
+        });
+        round_trip(MessageType::Command {
+            name: "name".to_string(),
+            args: vec!["user2".to_string()],
+        });
+    }
+
+    #[test]
+    fn system_message_round_trip() {
+        round_trip(MessageType::SystemMessage(
+            "user1 has disconnected.".to_string(),
+        ));
+    }
+
+    #[test]
+    fn login_round_trip() {
+        // Passwords may contain the characters the old "username:password" format split on
+        round_trip(MessageType::Login {
+            username: "user1".to_string(),
+            password: "pass:word:1".to_string(),
+        });
+        round_trip(MessageType::LoginOk {
+            username: "user1".to_string(),
+            token: "0123abcd".to_string(),
+        });
+        round_trip(MessageType::LoginFailed {
+            remaining_attempts: 3,
+        });
+    }
+
+    #[test]
+    fn resume_round_trip() {
+        round_trip(MessageType::Resume {
+            token: "0123abcd".to_string(),
+            last_seen_id: Some(42),
+        });
+        round_trip(MessageType::Resume {
+            token: "0123abcd".to_string(),
+            last_seen_id: None,
+        });
+        round_trip(MessageType::ResumeFailed);
+    }
+
+    #[test]
+    fn register_round_trip() {
+        round_trip(MessageType::Register {
+            username: "user3".to_string(),
+            password: "correct horse".to_string(),
+            invite_code: None,
+        });
+        round_trip(MessageType::Register {
+            username: "user3".to_string(),
+            password: "correct horse".to_string(),
+            invite_code: Some("welcome".to_string()),
+        });
+        round_trip(MessageType::RegisterOk);
+        round_trip(MessageType::RegisterFailed {
+            reason: "Username is already taken".to_string(),
+        });
+    }
+
+    #[test]
+    fn edit_and_delete_round_trip() {
+        round_trip(MessageType::EditMessage {
+            id: 3,
+            content: "fixed typo".to_string(),
+        });
+        round_trip(MessageType::DeleteMessage { id: 3 });
+        round_trip(MessageType::MessageEdited {
+            id: 3,
+            room: DEFAULT_ROOM.to_string(),
+            content: "fixed typo @user2".to_string(),
+            mentions: vec!["user2".to_string()],
+            edited_at: timestamp(),
+        });
+        round_trip(MessageType::MessageDeleted {
+            id: 3,
+            room: DEFAULT_ROOM.to_string(),
+        });
+    }
+
+    #[test]
+    fn reaction_round_trip() {
+        round_trip(MessageType::AddReaction {
+            id: 3,
+            emoji: "👍".to_string(),
+        });
+        round_trip(MessageType::RemoveReaction {
+            id: 3,
+            emoji: "👍".to_string(),
+        });
+        round_trip(MessageType::ReactionChanged {
+            id: 3,
+            room: DEFAULT_ROOM.to_string(),
+            emoji: "🎉".to_string(),
+            username: "user2".to_string(),
+            added: true,
+        });
+    }
+
+    #[test]
+    fn typing_round_trip() {
+        round_trip(MessageType::Typing {
+            room: DEFAULT_ROOM.to_string(),
+        });
+        round_trip(MessageType::UserTyping {
+            room: DEFAULT_ROOM.to_string(),
+            username: "user2".to_string(),
+            expires_in_ms: 6000,
+        });
+    }
+
+    #[test]
+    fn read_position_round_trip() {
+        round_trip(MessageType::MarkRead {
+            room: DEFAULT_ROOM.to_string(),
+            id: 7,
+        });
+        round_trip(MessageType::ReadPosition {
+            room: direct_room("user1", "user2"),
+            last_read: 3,
+        });
+    }
+
+    #[test]
+    fn history_round_trip() {
+        round_trip(MessageType::FetchHistory {
+            room: "#ops".to_string(),
+            before_id: None,
+            limit: 50,
+        });
+        round_trip(MessageType::FetchHistory {
+            room: "#ops".to_string(),
+            before_id: Some(42),
+            limit: 50,
+        });
+        round_trip(MessageType::History {
+            room: "#ops".to_string(),
+            messages: vec![MessageType::ChatMessage {
+                id: 41,
+                timestamp: timestamp(),
+                sender: "user1".to_string(),
+                content: "older".to_string(),
+                room: "#ops".to_string(),
+                recipient: None,
+                reply_to: None,
+                mentions: vec![],
+                edited_at: None,
+                deleted: true,
+                reactions: Reactions::from([("👍".to_string(), vec!["user2".to_string()])]),
+            }],
+            oldest_id: Some(41),
+            has_more: true,
+        });
+        round_trip(MessageType::History {
+            room: DEFAULT_ROOM.to_string(),
+            messages: vec![],
+            oldest_id: None,
+            has_more: false,
+        });
+    }
+
+    #[test]
+    fn room_round_trip() {
+        round_trip(MessageType::RoomJoined {
+            room: "#ops".to_string(),
+        });
+        round_trip(MessageType::RoomLeft {
+            room: "#ops".to_string(),
+        });
+        round_trip(MessageType::RoomList {
+            rooms: vec![RoomSummary {
+                name: DEFAULT_ROOM.to_string(),
+                members: 2,
+            }],
+        });
+    }
+
+    #[test]
+    fn wire_representation_is_externally_tagged() {
+        let message = MessageType::ChatMessage {
+            id: 7,
+            timestamp: timestamp(),
+            sender: "user1".to_string(),
+            content: "hi".to_string(),
+            room: "#general".to_string(),
+            recipient: None,
+            reply_to: None,
+            mentions: vec![],
+            edited_at: None,
+            deleted: false,
+            reactions: Reactions::new(),
+        };
+        assert_eq!(
+            message.to_json(),
+            r##"{"ChatMessage":{"id":7,"timestamp":"2024-10-01T12:30:00Z","sender":"user1","content":"hi","room":"#general","recipient":null,"reply_to":null,"mentions":[],"edited_at":null,"deleted":false,"reactions":{}}}"##
+        );
+        assert_eq!(
+            MessageType::SystemMessage("ok".to_string()).to_json(),
+            r#"{"SystemMessage":"ok"}"#
+        );
+        assert_eq!(MessageType::ResumeFailed.to_json(), r#""ResumeFailed""#);
+    }
+
+    #[test]
+    fn rejects_unknown_variants() {
+        assert!(MessageType::from_json(r#"{"Bogus":{}}"#).is_err());
+    }
+}
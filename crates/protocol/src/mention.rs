@@ -0,0 +1,42 @@
+//  This file contains the `@username` mention syntax shared by the client and server. The
+//  server decides which mentioned names are real users and lists them on the message; the
+//  client uses the same parsing to highlight them.
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+// `@name` mentions in `content` as (byte offset of the `@`, name) pairs. A mention starts
+// the text or follows a character that cannot be part of a name, so email addresses are
+// not mentions.
+pub fn find_mentions(content: &str) -> Vec<(usize, &str)> {
+    let mut mentions = Vec::new();
+    let mut previous = None;
+    for (index, c) in content.char_indices() {
+        if c == '@' && !previous.is_some_and(is_name_char) {
+            let rest = &content[index + 1..];
+            let len = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            if len > 0 {
+                mentions.push((index, &rest[..len]));
+            }
+        }
+        previous = Some(c);
+    }
+    mentions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_mentions() {
+        assert_eq!(
+            find_mentions("@alice can you ask @bob_2, or @carol-x?"),
+            vec![(0, "alice"), (19, "bob_2"), (30, "carol-x")]
+        );
+        assert_eq!(find_mentions("(@alice)"), vec![(1, "alice")]);
+        assert!(find_mentions("mail alice@example.com").is_empty());
+        assert!(find_mentions("just an @ sign").is_empty());
+    }
+}
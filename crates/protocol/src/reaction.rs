@@ -0,0 +1,64 @@
+//  This file contains the reaction rules shared by the client and server. Reactions are
+//  stored as emoji, so `:+1:` and 👍 count as the same reaction, and each message keeps the
+//  usernames behind every reaction so clients can tell which ones are their own.
+use std::collections::BTreeMap;
+
+// Emoji -> usernames who reacted with it, in the order they reacted
+pub type Reactions = BTreeMap<String, Vec<String>>;
+
+const MAX_REACTION_LEN: usize = 8;
+
+// Shortcodes understood by `normalize_reaction`
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("thumbsup", "👍"),
+    ("-1", "👎"),
+    ("thumbsdown", "👎"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("smile", "😄"),
+    ("tada", "🎉"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("rocket", "🚀"),
+    ("pray", "🙏"),
+    ("thinking", "🤔"),
+    ("white_check_mark", "✅"),
+];
+
+// Turn a shortcode such as ":+1:" into its emoji, or accept an emoji typed directly.
+// Returns `None` for unknown shortcodes and for plain text.
+pub fn normalize_reaction(input: &str) -> Option<String> {
+    let input = input.trim();
+    if let Some(code) = input
+        .strip_prefix(':')
+        .and_then(|rest| rest.strip_suffix(':'))
+    {
+        return SHORTCODES
+            .iter()
+            .find(|(name, _)| *name == code)
+            .map(|(_, emoji)| emoji.to_string());
+    }
+    let valid = !input.is_empty()
+        && input.chars().count() <= MAX_REACTION_LEN
+        && input
+            .chars()
+            .all(|c| !c.is_alphanumeric() && !c.is_whitespace() && !c.is_ascii());
+    valid.then(|| input.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_reactions() {
+        assert_eq!(normalize_reaction(":+1:"), Some("👍".to_string()));
+        assert_eq!(normalize_reaction(" 👍 "), Some("👍".to_string()));
+        assert_eq!(normalize_reaction(":heart:"), Some("❤️".to_string()));
+        assert_eq!(normalize_reaction(":nope:"), None);
+        assert_eq!(normalize_reaction("lol"), None);
+        assert_eq!(normalize_reaction(""), None);
+        assert_eq!(normalize_reaction(&"👍".repeat(9)), None);
+    }
+}
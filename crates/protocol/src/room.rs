@@ -0,0 +1,68 @@
+//  This file contains the room naming rules shared by the client and server, and the
+//  summary type used to list rooms. Direct messages are kept in a room of their own named
+//  after both participants, e.g. "@alice:bob", which cannot clash with "#" room names.
+use serde::{Deserialize, Serialize};
+
+// Every user is placed in this room when they log in
+pub const DEFAULT_ROOM: &str = "#general";
+const MAX_ROOM_NAME_LEN: usize = 32;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomSummary {
+    pub name: String,
+    pub members: u32,
+}
+
+// Turn user input such as "ops" or "#Ops" into a canonical room name ("#ops"),
+// or `None` if it is not a valid room name
+pub fn normalize_room_name(input: &str) -> Option<String> {
+    let name = input.trim().trim_start_matches('#').to_lowercase();
+    let valid = !name.is_empty()
+        && name.chars().count() < MAX_ROOM_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| format!("#{}", name))
+}
+
+// Room holding the direct messages between two users, the same whichever of them sends
+pub fn direct_room(user: &str, other: &str) -> String {
+    let (first, second) = if user <= other {
+        (user, other)
+    } else {
+        (other, user)
+    };
+    format!("@{}:{}", first, second)
+}
+
+// The two participants of a direct message room, or `None` for any other room
+pub fn direct_room_participants(room: &str) -> Option<(&str, &str)> {
+    room.strip_prefix('@')?.split_once(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_room_names() {
+        assert_eq!(normalize_room_name("ops"), Some("#ops".to_string()));
+        assert_eq!(normalize_room_name(" #Ops "), Some("#ops".to_string()));
+        assert_eq!(
+            normalize_room_name(DEFAULT_ROOM),
+            Some(DEFAULT_ROOM.to_string())
+        );
+        assert_eq!(normalize_room_name("#"), None);
+        assert_eq!(normalize_room_name("two words"), None);
+        assert_eq!(normalize_room_name(&"x".repeat(40)), None);
+        assert_eq!(normalize_room_name("@alice:bob"), None);
+    }
+
+    #[test]
+    fn direct_rooms_are_shared_by_both_participants() {
+        let room = direct_room("bob", "alice");
+        assert_eq!(room, direct_room("alice", "bob"));
+        assert_eq!(direct_room_participants(&room), Some(("alice", "bob")));
+        assert_eq!(direct_room_participants(DEFAULT_ROOM), None);
+    }
+}
@@ -1,125 +1,580 @@
 //  This file contains the definition of the `App` struct, which represents the server state.
-//  It also defines the `UserInfo` struct and an enumeration of message types.
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+//  It also defines the `UserInfo` struct, one per connected account; an account may have
+//  several sessions (connections). Message types live in the shared `protocol` crate.
+use crate::credentials::{CredentialError, CredentialStore};
+use crate::moderation::{check_can_modify, Moderators};
+use crate::nonces::RecentNonces;
+use crate::presence::{validate_display_name, Presence};
+use crate::registration::RegistrationMode;
+use crate::storage::{MessageStore, StorageError, StoredMessage};
+use crate::tokens::SessionTokens;
+use chrono::{DateTime, Utc};
+use protocol::{
+    direct_room_participants, find_mentions, normalize_reaction, MessageId, MessageType,
+    RoomSummary, DEFAULT_ROOM,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
+// Number of messages replayed to a client when it logs in
+pub const HISTORY_REPLAY_LIMIT: usize = 50;
+// Largest page a client may request with `FetchHistory`
+const MAX_HISTORY_PAGE: usize = 200;
+// How long clients show a typing indicator unless it is renewed
+pub const TYPING_EXPIRY: Duration = Duration::from_secs(6);
+// Typing events from the same user and room closer together than this are not passed on
+const TYPING_MIN_INTERVAL: Duration = Duration::from_secs(2);
+
 // App struct to store connected users and message history
 pub struct App {
-    // Store users with their UUID as key
+    // Session (connection) UUID -> username of the account it is logged in as
+    sessions: HashMap<String, String>,
+    // Username -> the account's connection details, for every account with a session
     connected_users: HashMap<String, Arc<Mutex<UserInfo>>>,
-    // Global message history (last 100 messages)
-    message_history: VecDeque<MessageType>,
-    user_credentials: HashMap<String, UserCredentials>, // Add this for storing credentials
+    // Room name -> usernames of its members. Membership outlives connections so a user
+    // is back in the same rooms when they reconnect.
+    rooms: BTreeMap<String, HashSet<String>>,
+    // Per-room message history, persisted by the configured store
+    message_store: Box<dyn MessageStore>,
+    credentials: CredentialStore,   // Accounts and their password hashes
+    registration: RegistrationMode, // Whether new accounts may be created by clients
+    moderators: Moderators,         // Users who may edit and delete other people's messages
+    // (room, username) -> when their last typing event was passed on
+    typing: HashMap<(String, String), Instant>,
+    tokens: SessionTokens, // Session tokens for resuming after a dropped connection
+    sent_nonces: RecentNonces, // Nonces of recently stored messages, to spot messages sent again
 }
 
 pub struct UserInfo {
     pub username: String,
-    pub connection_time: SystemTime,
-    pub message_count: usize,
-}
-
-pub struct UserCredentials {
-    pub username: String,
-    pub password: String, // Ideally store hashed passwords
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum MessageType {
-    ChatMessage { sender: String, content: String },
-    Command { name: String, args: Vec<String> },
-    SystemMessage(String),
+    pub message_count: usize, // room messages sent since the account connected, shown by `list`
+    pub display_name: Option<String>, // set with the `name` command, shared by every session
+    // Session UUID -> presence set on it with the `away`, `dnd` and `back` commands
+    pub sessions: HashMap<String, Presence>,
 }
 
 impl App {
-    pub fn new() -> App {
-        let mut user_credentials = HashMap::new();
-
-        // For simplicity, let's add a couple of users (these should be hashed passwords)
-        user_credentials.insert(
-            "user1".to_string(),
-            UserCredentials {
-                username: "user1".to_string(),
-                password: "password1".to_string(),
-            },
-        );
-        user_credentials.insert(
-            "user2".to_string(),
-            UserCredentials {
-                username: "user2".to_string(),
-                password: "password2".to_string(),
-            },
-        );
-
+    pub fn new(
+        credentials: CredentialStore,
+        registration: RegistrationMode,
+        moderators: Moderators,
+        message_store: Box<dyn MessageStore>,
+    ) -> App {
         App {
+            sessions: HashMap::new(),
             connected_users: HashMap::new(),
-            message_history: VecDeque::with_capacity(100), // Store up to 100 messages
-            user_credentials,                              // finitialize the credentials
+            rooms: BTreeMap::new(),
+            message_store,
+            credentials,
+            registration,
+            moderators,
+            typing: HashMap::new(),
+            tokens: SessionTokens::default(),
+            sent_nonces: RecentNonces::default(),
         }
     }
 
-    // Method to verify username and password
-    pub fn authenticate_user(&self, username: &str, password: &str) -> bool {
-        if let Some(credentials) = self.user_credentials.get(username) {
-            return credentials.password == password; // Verify credentials (ideally hash comparison)
+    // Fetch the stored password hash for a user, picking up any changes made to the
+    // credential file since it was loaded. Verification happens outside the App lock.
+    pub fn password_hash(&mut self, username: &str) -> Option<String> {
+        if let Err(e) = self.credentials.refresh() {
+            println!("Failed to reload credentials: {}", e);
         }
-        false
+        self.credentials.password_hash(username)
+    }
+
+    pub fn registration(&self) -> &RegistrationMode {
+        &self.registration
     }
 
-    // Add a connected user by UUID
+    pub fn has_account(&mut self, username: &str) -> bool {
+        if let Err(e) = self.credentials.refresh() {
+            println!("Failed to reload credentials: {}", e);
+        }
+        self.credentials.contains(username)
+    }
+
+    // Persist a newly registered account with an already hashed password
+    pub fn add_account(
+        &mut self,
+        username: &str,
+        password_hash: String,
+    ) -> Result<(), CredentialError> {
+        self.credentials.add_user_hash(username, password_hash)
+    }
+
+    // A new session token for a user who just logged in
+    pub fn issue_token(&mut self, username: &str) -> String {
+        self.tokens.issue(username, Instant::now())
+    }
+
+    // The account a session token resumes, unless the token expired or the account has
+    // since been removed
+    pub fn resume_session(&mut self, token: &str) -> Option<String> {
+        let username = self.tokens.resume(token, Instant::now())?;
+        self.has_account(&username).then_some(username)
+    }
+
+    // Add a session by UUID, joining the account's other sessions if it has any
     pub async fn add_connected_user(&mut self, user_id: String, username: String) {
-        let user_info = Arc::new(Mutex::new(UserInfo {
-            username,
-            connection_time: SystemTime::now(),
-            message_count: 0,
-        }));
-        self.connected_users.insert(user_id.clone(), user_info);
+        let user_info = self
+            .connected_users
+            .entry(username.clone())
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(UserInfo {
+                    username: username.clone(),
+                    message_count: 0,
+                    display_name: None,
+                    sessions: HashMap::new(),
+                }))
+            });
+        user_info
+            .lock()
+            .await
+            .sessions
+            .insert(user_id.clone(), Presence::Online);
+        self.sessions.insert(user_id, username);
     }
 
-    // Retrieve a connected user by UUID
+    // Retrieve the account behind a session UUID
     pub async fn get_connected_user(&self, user_id: &str) -> Option<Arc<Mutex<UserInfo>>> {
-        self.connected_users.get(user_id).cloned()
+        let username = self.sessions.get(user_id)?;
+        self.connected_users.get(username).cloned()
+    }
+
+    // Remove a session by UUID. When it was the account's last session, the account goes
+    // offline: it is recorded as last seen now and its username is returned.
+    pub async fn remove_connected_user(&mut self, user_id: &str) -> Option<String> {
+        let username = self.sessions.remove(user_id)?;
+        let user = self.connected_users.get(&username)?;
+        let mut user_lock = user.lock().await;
+        user_lock.sessions.remove(user_id);
+        if !user_lock.sessions.is_empty() {
+            return None;
+        }
+        drop(user_lock);
+        self.connected_users.remove(&username);
+        if let Err(e) = self.message_store.set_last_seen(&username, Utc::now()) {
+            println!("Failed to record when {} was last seen: {}", username, e);
+        }
+        Some(username)
     }
 
-    // Remove a connected user by UUID
-    pub async fn remove_connected_user(&mut self, user_id: &str) -> Option<Arc<Mutex<UserInfo>>> {
-        self.connected_users.remove(user_id)
+    // Set the presence of one session; `list` shows the account's combined presence
+    pub async fn set_presence(&self, user_id: &str, presence: Presence) {
+        if let Some(user) = self.get_connected_user(user_id).await {
+            user.lock()
+                .await
+                .sessions
+                .insert(user_id.to_string(), presence);
+        }
+    }
+
+    // Count a room message sent from one of the account's sessions
+    pub async fn count_sent_message(&self, user_id: &str) {
+        if let Some(user) = self.get_connected_user(user_id).await {
+            user.lock().await.message_count += 1;
+        }
+    }
+
+    // Accounts with no connected session, sorted by name, with when they were last seen
+    pub async fn offline_users(&self) -> Vec<(String, Option<DateTime<Utc>>)> {
+        let last_seen = self.message_store.last_seen().unwrap_or_else(|e| {
+            println!("Failed to load last-seen times: {}", e);
+            HashMap::new()
+        });
+        let mut offline: Vec<(String, Option<DateTime<Utc>>)> = self
+            .credentials
+            .usernames()
+            .filter(|username| !self.connected_users.contains_key(*username))
+            .map(|username| (username.clone(), last_seen.get(username).copied()))
+            .collect();
+        offline.sort();
+        offline
     }
 
     pub async fn get_connected_users(&self) -> Vec<Arc<Mutex<UserInfo>>> {
         self.connected_users.values().cloned().collect()
     }
 
-    // Update username for a user
-    pub async fn update_username(&mut self, user_id: String, username: String) {
-        if let Some(user_info) = self.connected_users.get_mut(&user_id) {
-            user_info.lock().await.username = username;
+    // Set the display name of the account behind a session, returning its username. The
+    // username stays the account's identity for logins, rooms, direct messages and
+    // moderation; the display name is only shown in `list` while the account is connected.
+    pub async fn set_display_name(&self, user_id: &str, name: &str) -> Result<String, String> {
+        let display_name = validate_display_name(name)?;
+        let Some(user) = self.get_connected_user(user_id).await else {
+            return Err("You are not logged in.".to_string());
+        };
+        let mut user_lock = user.lock().await;
+        user_lock.display_name = Some(display_name);
+        Ok(user_lock.username.clone())
+    }
+
+    // Rooms the user belongs to, placing them in the default room if they have none
+    pub async fn rooms_for_user(&mut self, username: &str) -> Vec<String> {
+        let rooms: Vec<String> = self
+            .rooms
+            .iter()
+            .filter(|(_, members)| members.contains(username))
+            .map(|(room, _)| room.clone())
+            .collect();
+        if rooms.is_empty() {
+            self.join_room(username, DEFAULT_ROOM).await;
+            return vec![DEFAULT_ROOM.to_string()];
+        }
+        rooms
+    }
+
+    // Add the user to a room, returning false if they were already a member
+    pub async fn join_room(&mut self, username: &str, room: &str) -> bool {
+        self.rooms
+            .entry(room.to_string())
+            .or_default()
+            .insert(username.to_string())
+    }
+
+    // Remove the user from a room, dropping the room once it is empty
+    pub async fn leave_room(&mut self, username: &str, room: &str) -> bool {
+        let Some(members) = self.rooms.get_mut(room) else {
+            return false;
+        };
+        let removed = members.remove(username);
+        if members.is_empty() {
+            self.rooms.remove(room);
         }
+        removed
     }
 
-    // Add a message to the message history (limit to 100 messages)
-    pub async fn add_message_to_history(&mut self, message: MessageType) {
-        if self.message_history.len() == 100 {
-            self.message_history.pop_front(); // Remove oldest message if full
+    pub async fn is_room_member(&self, username: &str, room: &str) -> bool {
+        self.rooms
+            .get(room)
+            .is_some_and(|members| members.contains(username))
+    }
+
+    // Whether the user may read a room's history: rooms they belong to and their own
+    // direct message conversations
+    pub async fn can_read_room(&self, username: &str, room: &str) -> bool {
+        match direct_room_participants(room) {
+            Some((first, second)) => username == first || username == second,
+            None => self.is_room_member(username, room).await,
+        }
+    }
+
+    // Direct message conversations the user has taken part in
+    pub async fn direct_rooms_for_user(&self, username: &str) -> Vec<String> {
+        match self.message_store.rooms() {
+            Ok(rooms) => rooms
+                .into_iter()
+                .filter(|room| {
+                    direct_room_participants(room)
+                        .is_some_and(|(first, second)| username == first || username == second)
+                })
+                .collect(),
+            Err(e) => {
+                println!("Failed to list direct message rooms: {}", e);
+                Vec::new()
+            }
         }
-        self.message_history.push_back(message);
     }
 
-    // Retrieve the message history
-    pub async fn get_message_history(&self) -> Vec<MessageType> {
-        self.message_history.iter().cloned().collect()
+    // Connection IDs of every session logged in as `username`
+    pub async fn user_sessions(&self, username: &str) -> Vec<String> {
+        self.sessions
+            .iter()
+            .filter(|(_, session_user)| *session_user == username)
+            .map(|(client_id, _)| client_id.clone())
+            .collect()
+    }
+
+    // Connection IDs of every session whose user is a member of `room`
+    pub async fn room_recipients(&self, room: &str) -> Vec<String> {
+        let Some(members) = self.rooms.get(room) else {
+            return Vec::new();
+        };
+        self.sessions
+            .iter()
+            .filter(|(_, username)| members.contains(*username))
+            .map(|(client_id, _)| client_id.clone())
+            .collect()
+    }
+
+    pub async fn list_rooms(&self) -> Vec<RoomSummary> {
+        self.rooms
+            .iter()
+            .map(|(name, members)| RoomSummary {
+                name: name.clone(),
+                members: members.len() as u32,
+            })
+            .collect()
+    }
+
+    // Users mentioned in `content`: an `@name` counts when it names an account or a
+    // connected user
+    pub async fn mentioned_users(&self, content: &str) -> Vec<String> {
+        let mut users: Vec<String> = Vec::new();
+        for (_, name) in find_mentions(content) {
+            if users.iter().any(|user| user == name) {
+                continue;
+            }
+            if self.credentials.contains(name) || !self.user_sessions(name).await.is_empty() {
+                users.push(name.to_string());
+            }
+        }
+        users
+    }
+
+    // The `ChatMessage` sent to clients for a stored message
+    pub async fn chat_message(&self, message: StoredMessage) -> MessageType {
+        let mentions = self.mentioned_users(&message.content).await;
+        message.into_chat_message(mentions)
+    }
+
+    // Record that `username` is typing in `room`, returning whether the event should be
+    // passed on. Repeated events within `TYPING_MIN_INTERVAL` are dropped.
+    pub fn note_typing(&mut self, room: &str, username: &str) -> bool {
+        let now = Instant::now();
+        self.typing
+            .retain(|_, last| now.duration_since(*last) < TYPING_EXPIRY);
+        let key = (room.to_string(), username.to_string());
+        match self.typing.get(&key) {
+            Some(last) if now.duration_since(*last) < TYPING_MIN_INTERVAL => false,
+            _ => {
+                self.typing.insert(key, now);
+                true
+            }
+        }
+    }
+
+    // The user's message arrived, so their next typing event starts a new indicator
+    pub fn stop_typing(&mut self, room: &str, username: &str) {
+        self.typing
+            .remove(&(room.to_string(), username.to_string()));
+    }
+
+    // Record how far the user has read a room they can see; other rooms are ignored
+    pub async fn mark_read(&mut self, username: &str, room: &str, id: MessageId) {
+        if !self.can_read_room(username, room).await {
+            return;
+        }
+        if let Err(e) = self.message_store.set_last_read(username, room, id) {
+            println!("Failed to record read position for {}: {}", username, e);
+        }
+    }
+
+    // Room -> newest message the user has read there
+    pub async fn read_positions(&self, username: &str) -> HashMap<String, MessageId> {
+        self.message_store.last_read(username).unwrap_or_else(|e| {
+            println!("Failed to load read positions for {}: {}", username, e);
+            HashMap::new()
+        })
+    }
+
+    // Add a chat message to the persistent history, returning the stored record
+    pub async fn add_message_to_history(
+        &mut self,
+        room: &str,
+        sender: &str,
+        content: &str,
+        reply_to: Option<MessageId>,
+    ) -> Result<StoredMessage, StorageError> {
+        self.message_store.append(room, sender, content, reply_to)
+    }
+
+    // The ID and timestamp of the message `username` already sent with `nonce`. Clients send
+    // a message again when the connection dropped before its acknowledgement arrived.
+    pub fn already_sent(&self, username: &str, nonce: &str) -> Option<(MessageId, DateTime<Utc>)> {
+        self.sent_nonces.get(username, nonce)
+    }
+
+    pub fn remember_sent(&mut self, username: &str, nonce: &str, stored: &StoredMessage) {
+        self.sent_nonces
+            .insert(username, nonce, stored.id, stored.timestamp);
+    }
+
+    // The thread a reply to `reply_to` belongs to. Threads are one level deep, so replying
+    // to a reply joins the thread of its parent.
+    pub async fn thread_parent(
+        &self,
+        room: &str,
+        reply_to: Option<MessageId>,
+    ) -> Result<Option<MessageId>, String> {
+        let Some(id) = reply_to else {
+            return Ok(None);
+        };
+        match self.message_store.get(id) {
+            Ok(Some(message)) if message.room == room => Ok(Some(message.reply_to.unwrap_or(id))),
+            Ok(_) => Err(format!("There is no message #{} in {}.", id, room)),
+            Err(e) => {
+                println!("Failed to load message {}: {}", id, e);
+                Err("The message could not be loaded.".to_string())
+            }
+        }
+    }
+
+    // Connection IDs that should see changes to messages in `room`: the room's members, or
+    // both participants of a direct message conversation
+    pub async fn message_recipients(&self, room: &str) -> Vec<String> {
+        match direct_room_participants(room) {
+            Some((first, second)) => {
+                let mut sessions = self.user_sessions(first).await;
+                sessions.extend(self.user_sessions(second).await);
+                sessions
+            }
+            None => self.room_recipients(room).await,
+        }
+    }
+
+    // Replace the content of a message on behalf of `username`. Errors are user-facing reasons.
+    pub async fn edit_message(
+        &mut self,
+        username: &str,
+        id: MessageId,
+        content: &str,
+    ) -> Result<StoredMessage, String> {
+        if content.trim().is_empty() {
+            return Err("A message cannot be empty; delete it instead.".to_string());
+        }
+        self.modifiable_message(username, id)?;
+        match self.message_store.edit(id, content) {
+            Ok(Some(message)) => Ok(message),
+            Ok(None) => Err(format!("There is no message #{}.", id)),
+            Err(e) => {
+                println!("Failed to edit message {}: {}", id, e);
+                Err("The message could not be edited.".to_string())
+            }
+        }
+    }
+
+    // Delete a message on behalf of `username`, leaving a tombstone in its place
+    pub async fn delete_message(
+        &mut self,
+        username: &str,
+        id: MessageId,
+    ) -> Result<StoredMessage, String> {
+        self.modifiable_message(username, id)?;
+        match self.message_store.delete(id) {
+            Ok(Some(message)) => Ok(message),
+            Ok(None) => Err(format!("There is no message #{}.", id)),
+            Err(e) => {
+                println!("Failed to delete message {}: {}", id, e);
+                Err("The message could not be deleted.".to_string())
+            }
+        }
+    }
+
+    // Add or remove `username`'s reaction to a message they can see. Returns the message
+    // and the normalized emoji, or `None` when the reaction was already in that state
+    pub async fn react(
+        &mut self,
+        username: &str,
+        id: MessageId,
+        emoji: &str,
+        add: bool,
+    ) -> Result<Option<(StoredMessage, String)>, String> {
+        let Some(emoji) = normalize_reaction(emoji) else {
+            return Err(format!(
+                "'{}' is not a reaction; use an emoji or :shortcode:.",
+                emoji
+            ));
+        };
+        let message = match self.message_store.get(id) {
+            Ok(Some(message)) if self.can_read_room(username, &message.room).await => message,
+            Ok(_) => return Err(format!("There is no message #{}.", id)),
+            Err(e) => {
+                println!("Failed to load message {}: {}", id, e);
+                return Err("The message could not be loaded.".to_string());
+            }
+        };
+        if message.deleted {
+            return Err("That message has been deleted.".to_string());
+        }
+        match self.message_store.set_reaction(id, &emoji, username, add) {
+            Ok(true) => Ok(Some((message, emoji))),
+            Ok(false) => Ok(None),
+            Err(e) => {
+                println!("Failed to update reactions on message {}: {}", id, e);
+                Err("The reaction could not be saved.".to_string())
+            }
+        }
+    }
+
+    fn modifiable_message(&self, username: &str, id: MessageId) -> Result<StoredMessage, String> {
+        let message = match self.message_store.get(id) {
+            Ok(Some(message)) => message,
+            Ok(None) => return Err(format!("There is no message #{}.", id)),
+            Err(e) => {
+                println!("Failed to load message {}: {}", id, e);
+                return Err("The message could not be loaded.".to_string());
+            }
+        };
+        check_can_modify(&self.moderators, username, &message)?;
+        Ok(message)
+    }
+
+    // Retrieve a page of a room's history older than `before_id` (the newest page when
+    // `None`) as a `History` message ready to send to a client
+    pub async fn get_message_history(
+        &self,
+        room: &str,
+        before_id: Option<MessageId>,
+        limit: usize,
+    ) -> MessageType {
+        let limit = limit.clamp(1, MAX_HISTORY_PAGE);
+        // Ask for one extra message to find out whether there is anything further back
+        let mut page = match self.message_store.page(room, before_id, limit + 1) {
+            Ok(page) => page,
+            Err(e) => {
+                println!("Failed to load message history: {}", e);
+                Vec::new()
+            }
+        };
+        let has_more = page.len() > limit;
+        if has_more {
+            page.remove(0);
+        }
+
+        let oldest_id = page.first().map(|message| message.id);
+        let mut messages = Vec::with_capacity(page.len());
+        for message in page {
+            messages.push(self.chat_message(message).await);
+        }
+        MessageType::History {
+            room: room.to_string(),
+            messages,
+            oldest_id,
+            has_more,
+        }
+    }
+
+    // The newest page of a room's history for a client that just logged in, widened for a
+    // resumed session to reach back to `last_seen_id` so everything it missed is included, up
+    // to `MAX_HISTORY_PAGE`. Each message is sent as it is now, so edits, deletions and
+    // reactions made while the client was away arrive with it.
+    pub async fn catch_up_history(
+        &self,
+        room: &str,
+        last_seen_id: Option<MessageId>,
+    ) -> MessageType {
+        let missed = match last_seen_id {
+            Some(after_id) => match self.message_store.since(room, after_id, MAX_HISTORY_PAGE) {
+                Ok(missed) => missed.len(),
+                Err(e) => {
+                    println!("Failed to load missed messages: {}", e);
+                    0
+                }
+            },
+            None => 0,
+        };
+        self.get_message_history(room, None, missed.max(HISTORY_REPLAY_LIMIT))
+            .await
     }
 }
 
 impl UserInfo {
-    pub fn new() -> UserInfo {
-        // Initalize and return a new isntance of 'UserInfo'
-        UserInfo {
-            username: "username".to_string(),
-            connection_time: SystemTime::now(),
-            message_count: 0,
-        }
+    // Presence across all of the account's sessions
+    pub fn presence(&self) -> Presence {
+        Presence::aggregate(self.sessions.values())
     }
 }
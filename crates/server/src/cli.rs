@@ -0,0 +1,70 @@
+//  This file contains the `server user ...` subcommands used to manage accounts in the
+//  credential file without starting the server or recompiling.
+use crate::credentials::CredentialStore;
+use std::error::Error;
+use std::io::{self, BufRead};
+
+const USAGE: &str = "Usage:
+  server user add <username> [--password-stdin]
+  server user passwd <username> [--password-stdin]
+  server user remove <username>
+  server user list";
+
+pub fn run_user_command(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut store = CredentialStore::load(CredentialStore::default_path())?;
+    let password_stdin = args.iter().any(|arg| arg == "--password-stdin");
+    let positional: Vec<&str> = args
+        .iter()
+        .map(String::as_str)
+        .filter(|arg| !arg.starts_with("--"))
+        .collect();
+
+    match positional.as_slice() {
+        ["add", username] => {
+            let password = read_new_password(password_stdin)?;
+            store.add_user(username, &password)?;
+            println!("Added user '{}' to {}", username, store.path().display());
+        }
+        ["passwd", username] => {
+            if !store.contains(username) {
+                return Err(format!("user '{}' does not exist", username).into());
+            }
+            let password = read_new_password(password_stdin)?;
+            store.set_password(username, &password)?;
+            println!("Updated password for '{}'", username);
+        }
+        ["remove", username] => {
+            store.remove_user(username)?;
+            println!("Removed user '{}'", username);
+        }
+        ["list"] => {
+            for username in store.usernames() {
+                println!("{}", username);
+            }
+        }
+        _ => return Err(USAGE.into()),
+    }
+
+    Ok(())
+}
+
+// Prompt twice on the terminal, or read a single line from stdin for scripted use
+fn read_new_password(from_stdin: bool) -> Result<String, Box<dyn Error>> {
+    let password = if from_stdin {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    } else {
+        let password = rpassword::prompt_password("New password: ")?;
+        let confirmation = rpassword::prompt_password("Confirm password: ")?;
+        if password != confirmation {
+            return Err("passwords do not match".into());
+        }
+        password
+    };
+
+    if password.is_empty() {
+        return Err("password cannot be empty".into());
+    }
+    Ok(password)
+}
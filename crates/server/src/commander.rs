@@ -1,7 +1,11 @@
 //  This file contains functions related to handling commands from clients. It includes a function
 //  for handling commands and sending messages to clients.
 pub mod command_handler {
-    use crate::app::{App, MessageType};
+    use crate::app::{App, HISTORY_REPLAY_LIMIT};
+    use crate::presence::{describe_last_seen, describe_user, Presence};
+    use crate::websocket::send_to_clients;
+    use chrono::Utc;
+    use protocol::{direct_room, normalize_room_name, MessageType};
     use std::collections::HashMap;
     use std::sync::Arc;
     use tokio::sync::{mpsc, Mutex};
@@ -20,25 +24,39 @@ pub mod command_handler {
 
         match command_name.as_str() {
             "name" => {
-                if let Some(new_name) = args.get(0) {
-                    // Update client name in the App (UserInfo)
-                    app.lock()
-                        .await
-                        .update_username(client_id.to_string(), new_name.clone())
-                        .await;
+                if let Some(new_name) = args.first() {
+                    // Set the account's display name, which every one of its sessions shares
+                    let app_lock = app.lock().await;
+                    match app_lock.set_display_name(client_id, new_name).await {
+                        Ok(username) => {
+                            let own_sessions = app_lock.user_sessions(&username).await;
+                            drop(app_lock);
+                            let display_name = new_name.trim();
+                            println!("{} is now shown as {}", username, display_name);
 
-                    // Notify client of the name change
-                    let system_message = MessageType::SystemMessage(format!(
-                        "Your name is now set to '{}'",
-                        new_name
-                    ));
-                    clients
-                        .lock()
-                        .await
-                        .get(client_id)
-                        .unwrap()
-                        .send(system_message)
-                        .unwrap();
+                            let system_message = MessageType::SystemMessage(format!(
+                                "Your display name is now set to '{}'",
+                                display_name
+                            ));
+                            let announcement = MessageType::SystemMessage(format!(
+                                "{} is now shown as {}.",
+                                username, display_name
+                            ));
+                            for (id, tx) in clients.lock().await.iter() {
+                                if own_sessions.contains(id) {
+                                    let _ = tx.send(system_message.clone());
+                                } else {
+                                    let _ = tx.send(announcement.clone());
+                                }
+                            }
+                        }
+                        Err(reason) => {
+                            drop(app_lock);
+                            if let Some(sender) = clients.lock().await.get(client_id) {
+                                let _ = sender.send(MessageType::SystemMessage(reason));
+                            }
+                        }
+                    }
                 }
             }
             "list" => {
@@ -46,21 +64,188 @@ pub mod command_handler {
                 let app_lock = app_clone.lock().await;
                 let connected_users = app_lock.get_connected_users().await;
 
-                // Collect usernames from App's connected_users
+                // Collect usernames, presence and message counts from App's connected_users
                 let mut names = Vec::new();
                 for user in connected_users.iter() {
                     let user_lock = user.lock().await;
-                    names.push(user_lock.username.clone());
+                    let name =
+                        describe_user(&user_lock.username, user_lock.display_name.as_deref());
+                    let sent = match user_lock.message_count {
+                        1 => "1 message".to_string(),
+                        count => format!("{} messages", count),
+                    };
+                    names.push(match user_lock.presence().describe() {
+                        Some(presence) => format!("{} ({}, {})", name, presence, sent),
+                        None => format!("{} ({})", name, sent),
+                    });
                 }
+                names.sort();
 
-                let names_string = names.join(", ");
-                let system_message =
-                    MessageType::SystemMessage(format!("Connected users: {}", names_string));
+                // Accounts without a session, with how long ago they were last connected
+                let now = Utc::now();
+                let offline: Vec<String> = app_lock
+                    .offline_users()
+                    .await
+                    .into_iter()
+                    .map(|(username, last_seen)| match last_seen {
+                        Some(last_seen) => format!(
+                            "{} (last seen {})",
+                            username,
+                            describe_last_seen(last_seen, now)
+                        ),
+                        None => format!("{} (never seen)", username),
+                    })
+                    .collect();
+                drop(app_lock);
+
+                let mut report = format!("Connected users: {}", names.join(", "));
+                if !offline.is_empty() {
+                    report.push_str(&format!("\nOffline: {}", offline.join(", ")));
+                }
+                let system_message = MessageType::SystemMessage(report);
 
                 if let Some(sender) = clients.lock().await.get(client_id) {
                     sender.send(system_message).unwrap();
                 }
             }
+            "DirectMessage" => {
+                let (Some(recipient), Some(content)) = (args.first(), args.get(1)) else {
+                    if let Some(sender) = clients.lock().await.get(client_id) {
+                        let _ = sender.send(MessageType::SystemMessage(
+                            "Usage: /dm <user> <message>".to_string(),
+                        ));
+                    }
+                    return;
+                };
+
+                let mut app_lock = app.lock().await;
+                let username = client_username(&app_lock, client_id).await;
+                let recipient_sessions = app_lock.user_sessions(recipient).await;
+                let error = if recipient == &username {
+                    Some("You cannot send a direct message to yourself.".to_string())
+                } else if recipient_sessions.is_empty() && !app_lock.has_account(recipient) {
+                    Some(format!("There is no user named '{}'.", recipient))
+                } else if recipient_sessions.is_empty() {
+                    Some(format!("{} is offline, message not sent.", recipient))
+                } else {
+                    None
+                };
+                if let Some(error) = error {
+                    drop(app_lock);
+                    if let Some(sender) = clients.lock().await.get(client_id) {
+                        let _ = sender.send(MessageType::SystemMessage(error));
+                    }
+                    return;
+                }
+
+                let room = direct_room(&username, recipient);
+                let message = match app_lock
+                    .add_message_to_history(&room, &username, content, None)
+                    .await
+                {
+                    Ok(stored) => app_lock.chat_message(stored).await,
+                    Err(e) => {
+                        drop(app_lock);
+                        println!("Failed to store direct message from {}: {}", username, e);
+                        if let Some(sender) = clients.lock().await.get(client_id) {
+                            let _ = sender.send(MessageType::SystemMessage(
+                                "Message could not be sent.".to_string(),
+                            ));
+                        }
+                        return;
+                    }
+                };
+
+                // Deliver to every session of the recipient, and back to the sender's
+                // sessions so the conversation shows on both sides
+                let mut sessions = recipient_sessions;
+                sessions.extend(app_lock.user_sessions(&username).await);
+                drop(app_lock);
+
+                send_to_clients(clients, &sessions, &message).await;
+            }
+            "join" => {
+                let reply = match args.first().and_then(|arg| normalize_room_name(arg)) {
+                    Some(room) => {
+                        let mut app_lock = app.lock().await;
+                        let username = client_username(&app_lock, client_id).await;
+                        if app_lock.join_room(&username, &room).await {
+                            // Membership belongs to the account, so every one of its sessions
+                            // gets the change followed by the room's recent history
+                            let history = app_lock
+                                .get_message_history(&room, None, HISTORY_REPLAY_LIMIT)
+                                .await;
+                            let sessions = app_lock.user_sessions(&username).await;
+                            drop(app_lock);
+                            send_to_clients(clients, &sessions, &MessageType::RoomJoined { room })
+                                .await;
+                            send_to_clients(clients, &sessions, &history).await;
+                            return;
+                        }
+                        MessageType::SystemMessage(format!("You are already in {}.", room))
+                    }
+                    None => MessageType::SystemMessage(
+                        "Usage: /join #room (letters, digits, '-' and '_')".to_string(),
+                    ),
+                };
+                if let Some(sender) = clients.lock().await.get(client_id) {
+                    let _ = sender.send(reply);
+                }
+            }
+            "leave" => {
+                let mut app_lock = app.lock().await;
+                let username = client_username(&app_lock, client_id).await;
+                let room = args.first().and_then(|arg| normalize_room_name(arg));
+                let rooms = app_lock.rooms_for_user(&username).await;
+
+                // Users always stay in at least one room so their messages have somewhere to go
+                let reply = match room {
+                    Some(room) if !rooms.contains(&room) => {
+                        MessageType::SystemMessage(format!("You are not in {}.", room))
+                    }
+                    Some(_) if rooms.len() == 1 => {
+                        MessageType::SystemMessage("You cannot leave your last room.".to_string())
+                    }
+                    Some(room) => {
+                        // Every session of the account leaves the room with it
+                        app_lock.leave_room(&username, &room).await;
+                        let sessions = app_lock.user_sessions(&username).await;
+                        drop(app_lock);
+                        send_to_clients(clients, &sessions, &MessageType::RoomLeft { room }).await;
+                        return;
+                    }
+                    None => MessageType::SystemMessage("Usage: /leave #room".to_string()),
+                };
+                drop(app_lock);
+
+                if let Some(sender) = clients.lock().await.get(client_id) {
+                    let _ = sender.send(reply);
+                }
+            }
+            "away" | "dnd" | "back" => {
+                let reason = Some(args.join(" ").trim().to_string()).filter(|r| !r.is_empty());
+                let (presence, reply) = match command_name.as_str() {
+                    "away" => (
+                        Presence::Away(reason.clone()),
+                        match reason {
+                            Some(reason) => format!("You are now away ({}).", reason),
+                            None => "You are now away.".to_string(),
+                        },
+                    ),
+                    "dnd" => (Presence::DoNotDisturb, "Do not disturb is on.".to_string()),
+                    _ => (Presence::Online, "You are back online.".to_string()),
+                };
+                app.lock().await.set_presence(client_id, presence).await;
+                if let Some(sender) = clients.lock().await.get(client_id) {
+                    let _ = sender.send(MessageType::SystemMessage(reply));
+                }
+            }
+            "rooms" => {
+                let rooms = app.lock().await.list_rooms().await;
+                if let Some(sender) = clients.lock().await.get(client_id) {
+                    let _ = sender.send(MessageType::RoomList { rooms });
+                }
+            }
             _ => {
                 let system_message = MessageType::SystemMessage(
                     "Unknown command. Type /help for a list of commands.".to_string(),
@@ -71,4 +256,12 @@ pub mod command_handler {
             }
         }
     }
+
+    // Username of the account behind a connection
+    async fn client_username(app: &App, client_id: &str) -> String {
+        match app.get_connected_user(client_id).await {
+            Some(user) => user.lock().await.username.clone(),
+            None => String::new(),
+        }
+    }
 }
@@ -0,0 +1,301 @@
+//  This file contains the `CredentialStore`, which keeps user accounts in a local JSON file.
+//  Passwords are stored as argon2 hashes (PHC strings) and are never kept in plaintext.
+//  The file is rewritten atomically on every change so a crash cannot leave it half written.
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+pub const DEFAULT_CREDENTIALS_FILE: &str = "users.json";
+const MAX_USERNAME_LEN: usize = 32;
+
+#[derive(Debug)]
+pub enum CredentialError {
+    Io(io::Error),
+    Parse(serde_json::Error),
+    Hash(argon2::password_hash::Error),
+    InvalidUsername(String),
+    UserExists(String),
+    UnknownUser(String),
+}
+
+impl fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CredentialError::Io(e) => write!(f, "credential file error: {}", e),
+            CredentialError::Parse(e) => write!(f, "credential file is malformed: {}", e),
+            CredentialError::Hash(e) => write!(f, "password hashing failed: {}", e),
+            CredentialError::InvalidUsername(reason) => write!(f, "invalid username: {}", reason),
+            CredentialError::UserExists(name) => write!(f, "user '{}' already exists", name),
+            CredentialError::UnknownUser(name) => write!(f, "user '{}' does not exist", name),
+        }
+    }
+}
+
+impl std::error::Error for CredentialError {}
+
+impl From<io::Error> for CredentialError {
+    fn from(e: io::Error) -> Self {
+        CredentialError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for CredentialError {
+    fn from(e: serde_json::Error) -> Self {
+        CredentialError::Parse(e)
+    }
+}
+
+impl From<argon2::password_hash::Error> for CredentialError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        CredentialError::Hash(e)
+    }
+}
+
+pub struct CredentialStore {
+    path: PathBuf,
+    // Username -> argon2 PHC hash string
+    users: BTreeMap<String, String>,
+    // Modification time of the file when it was last read, used to pick up external edits
+    loaded_at: Option<SystemTime>,
+}
+
+impl CredentialStore {
+    // Load the store from `path`; a missing file is treated as an empty store
+    pub fn load(path: impl Into<PathBuf>) -> Result<CredentialStore, CredentialError> {
+        let mut store = CredentialStore {
+            path: path.into(),
+            users: BTreeMap::new(),
+            loaded_at: None,
+        };
+        store.reload()?;
+        Ok(store)
+    }
+
+    // Location of the credential file, taken from `CREDENTIALS_FILE` or the default
+    pub fn default_path() -> PathBuf {
+        std::env::var("CREDENTIALS_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(DEFAULT_CREDENTIALS_FILE))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+
+    pub fn contains(&self, username: &str) -> bool {
+        self.users.contains_key(username)
+    }
+
+    pub fn usernames(&self) -> impl Iterator<Item = &String> {
+        self.users.keys()
+    }
+
+    // Re-read the file if it changed on disk since it was loaded (e.g. via `server user add`)
+    pub fn refresh(&mut self) -> Result<(), CredentialError> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        if modified != self.loaded_at {
+            self.reload()?;
+        }
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<(), CredentialError> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => {
+                self.users = serde_json::from_str(&contents)?;
+                self.loaded_at = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.users.clear();
+                self.loaded_at = None;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    // Look up the stored hash so it can be verified without holding the App lock
+    pub fn password_hash(&self, username: &str) -> Option<String> {
+        self.users.get(username).cloned()
+    }
+
+    pub fn add_user(&mut self, username: &str, password: &str) -> Result<(), CredentialError> {
+        let hash = hash_password(password)?;
+        self.add_user_hash(username, hash)
+    }
+
+    // Add an account whose password was already hashed with `hash_password`
+    pub fn add_user_hash(&mut self, username: &str, hash: String) -> Result<(), CredentialError> {
+        validate_username(username)?;
+        self.refresh()?;
+        if self.users.contains_key(username) {
+            return Err(CredentialError::UserExists(username.to_string()));
+        }
+        self.users.insert(username.to_string(), hash);
+        self.save()
+    }
+
+    pub fn set_password(&mut self, username: &str, password: &str) -> Result<(), CredentialError> {
+        self.refresh()?;
+        if !self.users.contains_key(username) {
+            return Err(CredentialError::UnknownUser(username.to_string()));
+        }
+        self.users
+            .insert(username.to_string(), hash_password(password)?);
+        self.save()
+    }
+
+    pub fn remove_user(&mut self, username: &str) -> Result<(), CredentialError> {
+        self.refresh()?;
+        if self.users.remove(username).is_none() {
+            return Err(CredentialError::UnknownUser(username.to_string()));
+        }
+        self.save()
+    }
+
+    // Write to a temporary file next to the target and rename it into place. The file is
+    // created readable by its owner only, so the hashes are never exposed to other users.
+    fn save(&mut self) -> Result<(), CredentialError> {
+        let contents = serde_json::to_string_pretty(&self.users)?;
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        // A leftover file would keep its old permissions, so always start from a new one
+        match fs::remove_file(&tmp_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&tmp_path)?.write_all(contents.as_bytes())?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.loaded_at = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+}
+
+pub fn validate_username(username: &str) -> Result<(), CredentialError> {
+    let invalid = |reason: &str| Err(CredentialError::InvalidUsername(reason.to_string()));
+    if username.is_empty() {
+        return invalid("username cannot be empty");
+    }
+    if username.chars().count() > MAX_USERNAME_LEN {
+        return invalid("username is too long");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return invalid("only letters, digits, '_' and '-' are allowed");
+    }
+    Ok(())
+}
+
+pub fn hash_password(password: &str) -> Result<String, CredentialError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+// Check a password against a stored hash. Unknown users are verified against a dummy hash so
+// that the response time does not reveal whether an account exists.
+pub fn verify_password(stored_hash: Option<&str>, password: &str) -> bool {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    let dummy = DUMMY_HASH
+        .get_or_init(|| hash_password("dummy password").expect("failed to hash dummy password"));
+
+    let (hash, known_user) = match stored_hash {
+        Some(hash) => (hash, true),
+        None => (dummy.as_str(), false),
+    };
+
+    let matches = match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    };
+    matches && known_user
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store() -> CredentialStore {
+        let path = std::env::temp_dir().join(format!("credentials-{}.json", uuid::Uuid::new_v4()));
+        CredentialStore::load(path).unwrap()
+    }
+
+    #[test]
+    fn add_verify_and_remove_user() {
+        let mut store = temp_store();
+        store.add_user("user1", "pass:word").unwrap();
+
+        let hash = store.password_hash("user1");
+        assert!(!hash.as_deref().unwrap().contains("pass:word"));
+        assert!(verify_password(hash.as_deref(), "pass:word"));
+        assert!(!verify_password(hash.as_deref(), "wrong"));
+        assert!(!verify_password(None, "pass:word"));
+
+        // A fresh load sees the persisted account
+        let reloaded = CredentialStore::load(store.path()).unwrap();
+        assert!(reloaded.contains("user1"));
+
+        store.remove_user("user1").unwrap();
+        assert!(!store.contains("user1"));
+        fs::remove_file(store.path()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saves_with_owner_only_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let mut store = temp_store();
+        store.add_user("user1", "password1").unwrap();
+        let mode = fs::metadata(store.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn rejects_duplicates_and_unknown_users() {
+        let mut store = temp_store();
+        store.add_user("user1", "password1").unwrap();
+        assert!(matches!(
+            store.add_user("user1", "password2"),
+            Err(CredentialError::UserExists(_))
+        ));
+        assert!(matches!(
+            store.set_password("user2", "password2"),
+            Err(CredentialError::UnknownUser(_))
+        ));
+        fs::remove_file(store.path()).unwrap();
+    }
+
+    #[test]
+    fn validates_usernames() {
+        assert!(validate_username("user_1-a").is_ok());
+        assert!(validate_username("").is_err());
+        assert!(validate_username("has space").is_err());
+        assert!(validate_username("a:b").is_err());
+        assert!(validate_username(&"x".repeat(33)).is_err());
+    }
+}
@@ -7,22 +7,67 @@ use tokio::sync::broadcast;
 use tokio::sync::Mutex;
 
 mod app;
+mod cli;
 mod commander;
+mod credentials;
+mod moderation;
+mod nonces;
+mod presence;
+mod registration;
+mod storage;
+mod tokens;
 mod websocket;
 use crate::app::App;
+use crate::credentials::CredentialStore;
+use crate::moderation::Moderators;
+use crate::registration::RegistrationMode;
 use crate::websocket::websocket_task;
 #[tokio::main]
 async fn main() {
+    // `server user ...` manages accounts instead of starting the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("user") {
+        if let Err(e) = cli::run_user_command(&args[1..]) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     // Load port from ENV or default to 8080
-    let port:u16 = std::env::var("PORT")
+    let port: u16 = std::env::var("PORT")
         .unwrap_or("8080".into())
         .parse()
         .expect("PORT must be a number");
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
 
+    // Load user accounts
+    let credentials =
+        CredentialStore::load(CredentialStore::default_path()).expect("Failed to load credentials");
+    if credentials.is_empty() {
+        println!(
+            "No accounts found in {}. Create one with `server user add <username>`.",
+            credentials.path().display()
+        );
+    }
+
+    let registration = RegistrationMode::from_env();
+    println!("Account registration is {}", registration.describe());
+
+    let moderators = Moderators::from_env();
+    println!("{} moderator(s) configured", moderators.count());
+
+    // Open the message history store
+    let message_store = storage::open_from_env().expect("Failed to open message history");
+
     // Initialize server state
-    let app = Arc::new(Mutex::new(App::new()));
+    let app = Arc::new(Mutex::new(App::new(
+        credentials,
+        registration,
+        moderators,
+        message_store,
+    )));
 
     // Channel to broadcast shutdown signal
     let (shutdown_tx, _) = broadcast::channel(1);
@@ -0,0 +1,89 @@
+//  This file contains the rules for changing messages after they are sent. Authors may edit
+//  or delete their own messages, and moderators, listed by username in the comma-separated
+//  `MODERATORS` environment variable, may do the same to any message posted in a room.
+use crate::storage::StoredMessage;
+use protocol::direct_room_participants;
+use std::collections::HashSet;
+
+pub struct Moderators(HashSet<String>);
+
+impl Moderators {
+    pub fn from_env() -> Moderators {
+        let moderators = std::env::var("MODERATORS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect();
+        Moderators(moderators)
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn contains(&self, username: &str) -> bool {
+        self.0.contains(username)
+    }
+}
+
+// Check that `username` may edit or delete `message`. Direct messages are private, so
+// moderators cannot change someone else's.
+pub fn check_can_modify(
+    moderators: &Moderators,
+    username: &str,
+    message: &StoredMessage,
+) -> Result<(), String> {
+    if message.deleted {
+        return Err("That message has been deleted.".to_string());
+    }
+    let is_direct = direct_room_participants(&message.room).is_some();
+    if message.sender == username || (moderators.contains(username) && !is_direct) {
+        Ok(())
+    } else {
+        Err("You can only change your own messages.".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use protocol::{direct_room, DEFAULT_ROOM};
+
+    fn message(room: &str, sender: &str) -> StoredMessage {
+        StoredMessage {
+            id: 1,
+            timestamp: Utc::now(),
+            room: room.to_string(),
+            sender: sender.to_string(),
+            content: "hello".to_string(),
+            reply_to: None,
+            edited_at: None,
+            deleted: false,
+            reactions: Default::default(),
+        }
+    }
+
+    #[test]
+    fn authors_and_moderators_may_modify() {
+        let moderators = Moderators(HashSet::from(["mod".to_string()]));
+        let room_message = message(DEFAULT_ROOM, "user1");
+        assert!(check_can_modify(&moderators, "user1", &room_message).is_ok());
+        assert!(check_can_modify(&moderators, "mod", &room_message).is_ok());
+        assert!(check_can_modify(&moderators, "user2", &room_message).is_err());
+
+        let direct_message = message(&direct_room("user1", "user2"), "user1");
+        assert!(check_can_modify(&moderators, "user1", &direct_message).is_ok());
+        assert!(check_can_modify(&moderators, "mod", &direct_message).is_err());
+        assert!(check_can_modify(&moderators, "user2", &direct_message).is_err());
+    }
+
+    #[test]
+    fn deleted_messages_cannot_be_modified() {
+        let moderators = Moderators(HashSet::new());
+        let mut deleted = message(DEFAULT_ROOM, "user1");
+        deleted.deleted = true;
+        assert!(check_can_modify(&moderators, "user1", &deleted).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+//  This file contains the nonces of recently stored messages. Clients send a message again
+//  when the connection drops before its `Ack` arrives, so a nonce seen before is answered
+//  with the original acknowledgement instead of storing the message twice. Only the most
+//  recent `MAX_RECENT_NONCES` are remembered, in memory.
+use chrono::{DateTime, Utc};
+use protocol::MessageId;
+use std::collections::{HashMap, VecDeque};
+
+const MAX_RECENT_NONCES: usize = 10_000;
+
+#[derive(Default)]
+pub struct RecentNonces {
+    // (username, nonce) -> ID and time the message was stored with
+    stored: HashMap<(String, String), (MessageId, DateTime<Utc>)>,
+    order: VecDeque<(String, String)>, // oldest first, to forget them in order
+}
+
+impl RecentNonces {
+    // The ID and timestamp of the message `username` already sent with `nonce`, if any
+    pub fn get(&self, username: &str, nonce: &str) -> Option<(MessageId, DateTime<Utc>)> {
+        self.stored
+            .get(&(username.to_string(), nonce.to_string()))
+            .copied()
+    }
+
+    pub fn insert(&mut self, username: &str, nonce: &str, id: MessageId, timestamp: DateTime<Utc>) {
+        let key = (username.to_string(), nonce.to_string());
+        if self.stored.insert(key.clone(), (id, timestamp)).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > MAX_RECENT_NONCES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.stored.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembers_nonces_per_user() {
+        let mut nonces = RecentNonces::default();
+        let now = Utc::now();
+        nonces.insert("user1", "abc", 7, now);
+
+        assert_eq!(nonces.get("user1", "abc"), Some((7, now)));
+        assert_eq!(nonces.get("user2", "abc"), None);
+        assert_eq!(nonces.get("user1", "def"), None);
+    }
+
+    #[test]
+    fn forgets_the_oldest_nonces() {
+        let mut nonces = RecentNonces::default();
+        let now = Utc::now();
+        for id in 0..=MAX_RECENT_NONCES as MessageId {
+            nonces.insert("user1", &id.to_string(), id, now);
+        }
+        assert_eq!(nonces.get("user1", "0"), None);
+        assert_eq!(nonces.get("user1", "1"), Some((1, now)));
+        assert_eq!(nonces.stored.len(), MAX_RECENT_NONCES);
+    }
+}
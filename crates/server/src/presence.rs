@@ -0,0 +1,156 @@
+//  This file contains the presence states users can set for their sessions, how the states
+//  of a user's sessions combine, the display names users can give themselves, and how these
+//  and last-seen times are described in the `list` command.
+use chrono::{DateTime, Utc};
+
+const MAX_DISPLAY_NAME_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Presence {
+    #[default]
+    Online,
+    Away(Option<String>), // optional reason, e.g. "lunch" or "idle"
+    DoNotDisturb,
+}
+
+impl Presence {
+    // Short description shown after the username, or `None` when simply online
+    pub fn describe(&self) -> Option<String> {
+        match self {
+            Presence::Online => None,
+            Presence::Away(None) => Some("away".to_string()),
+            Presence::Away(Some(reason)) => Some(format!("away: {}", reason)),
+            Presence::DoNotDisturb => Some("do not disturb".to_string()),
+        }
+    }
+
+    // A user's presence across all of their sessions: do not disturb on any session wins,
+    // then online on any session; they are only away when every session is
+    pub fn aggregate<'a>(sessions: impl IntoIterator<Item = &'a Presence>) -> Presence {
+        let mut combined: Option<Presence> = None;
+        for presence in sessions {
+            combined = match (combined, presence) {
+                (Some(Presence::DoNotDisturb), _) | (_, Presence::DoNotDisturb) => {
+                    Some(Presence::DoNotDisturb)
+                }
+                (Some(Presence::Online), _) | (_, Presence::Online) => Some(Presence::Online),
+                // Keep the first reason given
+                (Some(Presence::Away(Some(reason))), _) => Some(Presence::Away(Some(reason))),
+                (_, away) => Some(away.clone()),
+            };
+        }
+        combined.unwrap_or_default()
+    }
+}
+
+// Check a display name set with the `name` command, returning it without surrounding spaces
+pub fn validate_display_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Your display name cannot be empty.".to_string());
+    }
+    if name.chars().count() > MAX_DISPLAY_NAME_LEN {
+        return Err(format!(
+            "Display names can be at most {} characters.",
+            MAX_DISPLAY_NAME_LEN
+        ));
+    }
+    if name.chars().any(char::is_control) {
+        return Err("Display names cannot contain control characters.".to_string());
+    }
+    Ok(name.to_string())
+}
+
+// How a connected user is listed: their display name, if they set one, followed by the
+// username that identifies them
+pub fn describe_user(username: &str, display_name: Option<&str>) -> String {
+    match display_name {
+        Some(display_name) if display_name != username => {
+            format!("{} [{}]", display_name, username)
+        }
+        _ => username.to_string(),
+    }
+}
+
+// How long ago an offline user was last seen, e.g. "5m ago"
+pub fn describe_last_seen(last_seen: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let minutes = (now - last_seen).num_minutes().max(0);
+    match minutes {
+        0 => "just now".to_string(),
+        1..=59 => format!("{}m ago", minutes),
+        60..=1439 => format!("{}h ago", minutes / 60),
+        _ => format!("{}d ago", minutes / 1440),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn describes_presence() {
+        assert_eq!(Presence::Online.describe(), None);
+        assert_eq!(Presence::Away(None).describe(), Some("away".to_string()));
+        assert_eq!(
+            Presence::Away(Some("lunch".to_string())).describe(),
+            Some("away: lunch".to_string())
+        );
+        assert_eq!(
+            Presence::DoNotDisturb.describe(),
+            Some("do not disturb".to_string())
+        );
+    }
+
+    #[test]
+    fn aggregates_sessions() {
+        let away = Presence::Away(Some("lunch".to_string()));
+        assert_eq!(Presence::aggregate(&[]), Presence::Online);
+        assert_eq!(
+            Presence::aggregate(&[Presence::Away(None), away.clone()]),
+            away
+        );
+        assert_eq!(
+            Presence::aggregate(&[away.clone(), Presence::Online]),
+            Presence::Online
+        );
+        assert_eq!(
+            Presence::aggregate(&[Presence::Online, Presence::DoNotDisturb, away]),
+            Presence::DoNotDisturb
+        );
+    }
+
+    #[test]
+    fn validates_display_names() {
+        assert_eq!(validate_display_name("  Ada L. "), Ok("Ada L.".to_string()));
+        assert!(validate_display_name("   ").is_err());
+        assert!(validate_display_name(&"a".repeat(MAX_DISPLAY_NAME_LEN + 1)).is_err());
+        assert!(validate_display_name("bad\nname").is_err());
+    }
+
+    #[test]
+    fn describes_users() {
+        assert_eq!(describe_user("user1", None), "user1");
+        assert_eq!(describe_user("user1", Some("user1")), "user1");
+        assert_eq!(describe_user("user1", Some("Ada L.")), "Ada L. [user1]");
+    }
+
+    #[test]
+    fn describes_last_seen() {
+        let now = Utc::now();
+        assert_eq!(describe_last_seen(now, now), "just now");
+        assert_eq!(
+            describe_last_seen(now + Duration::seconds(5), now),
+            "just now"
+        );
+        assert_eq!(
+            describe_last_seen(now - Duration::minutes(5), now),
+            "5m ago"
+        );
+        assert_eq!(
+            describe_last_seen(now - Duration::minutes(185), now),
+            "3h ago"
+        );
+        assert_eq!(describe_last_seen(now - Duration::days(2), now), "2d ago");
+    }
+}
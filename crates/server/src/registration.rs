@@ -0,0 +1,150 @@
+//  This file contains the self-service registration policy. Operators choose whether new
+//  accounts can be created with the `REGISTRATION` environment variable:
+//  `closed` (default), `open`, or `invite` together with a comma-separated `INVITE_CODES` list.
+use crate::app::App;
+use crate::credentials::{hash_password, validate_username, CredentialError};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const MIN_PASSWORD_LEN: usize = 8;
+
+pub enum RegistrationMode {
+    Closed,
+    Open,
+    InviteOnly(HashSet<String>),
+}
+
+impl RegistrationMode {
+    pub fn from_env() -> RegistrationMode {
+        let mode = std::env::var("REGISTRATION").unwrap_or_default();
+        match mode.trim().to_lowercase().as_str() {
+            "open" => RegistrationMode::Open,
+            "invite" => {
+                let codes = std::env::var("INVITE_CODES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|code| code.trim().to_string())
+                    .filter(|code| !code.is_empty())
+                    .collect::<HashSet<String>>();
+                if codes.is_empty() {
+                    println!("REGISTRATION=invite but INVITE_CODES is empty; nobody can register.");
+                }
+                RegistrationMode::InviteOnly(codes)
+            }
+            "" | "closed" => RegistrationMode::Closed,
+            other => {
+                println!(
+                    "Unknown REGISTRATION mode '{}', registration is closed.",
+                    other
+                );
+                RegistrationMode::Closed
+            }
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            RegistrationMode::Closed => "closed",
+            RegistrationMode::Open => "open",
+            RegistrationMode::InviteOnly(_) => "invite only",
+        }
+    }
+
+    fn check_invite(&self, invite_code: Option<&str>) -> Result<(), String> {
+        match self {
+            RegistrationMode::Closed => Err("Registration is disabled on this server".to_string()),
+            RegistrationMode::Open => Ok(()),
+            RegistrationMode::InviteOnly(codes) => match invite_code.map(str::trim) {
+                Some(code) if codes.contains(code) => Ok(()),
+                Some(code) if !code.is_empty() => Err("Invalid invite code".to_string()),
+                _ => Err("An invite code is required to register".to_string()),
+            },
+        }
+    }
+}
+
+pub fn validate_password(username: &str, password: &str) -> Result<(), String> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(format!(
+            "Password must be at least {} characters",
+            MIN_PASSWORD_LEN
+        ));
+    }
+    if password.eq_ignore_ascii_case(username) {
+        return Err("Password cannot match the username".to_string());
+    }
+    let has_letter = password.chars().any(char::is_alphabetic);
+    let has_other = password.chars().any(|c| !c.is_alphabetic());
+    if !(has_letter && has_other) {
+        return Err("Password must mix letters with digits or symbols".to_string());
+    }
+    Ok(())
+}
+
+// Validate and persist a new account. Errors are user-facing reasons sent back to the client.
+pub async fn register_account(
+    app: &Arc<Mutex<App>>,
+    username: &str,
+    password: String,
+    invite_code: Option<&str>,
+) -> Result<(), String> {
+    {
+        let mut app = app.lock().await;
+        app.registration().check_invite(invite_code)?;
+        validate_username(username).map_err(|e| match e {
+            CredentialError::InvalidUsername(reason) => format!("Invalid username: {}", reason),
+            other => other.to_string(),
+        })?;
+        validate_password(username, &password)?;
+        if app.has_account(username) {
+            return Err("Username is already taken".to_string());
+        }
+    }
+
+    // Hash on the blocking pool so other connections are not stalled
+    let hash = tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|_| "Registration failed".to_string())?
+        .map_err(|e| {
+            println!("Failed to hash password: {}", e);
+            "Registration failed".to_string()
+        })?;
+
+    app.lock()
+        .await
+        .add_account(username, hash)
+        .map_err(|e| match e {
+            CredentialError::UserExists(_) => "Username is already taken".to_string(),
+            other => {
+                println!("Failed to save account '{}': {}", username, other);
+                "Registration failed".to_string()
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_rules() {
+        assert!(validate_password("user1", "short1").is_err());
+        assert!(validate_password("user1", "lettersonly").is_err());
+        assert!(validate_password("user1", "12345678").is_err());
+        assert!(validate_password("password1", "Password1").is_err());
+        assert!(validate_password("user1", "hunter2:ok").is_ok());
+    }
+
+    #[test]
+    fn invite_gate() {
+        let mode = RegistrationMode::InviteOnly(HashSet::from(["welcome".to_string()]));
+        assert!(mode.check_invite(Some("welcome")).is_ok());
+        assert!(mode.check_invite(Some("nope")).is_err());
+        assert!(mode.check_invite(None).is_err());
+        assert!(RegistrationMode::Open.check_invite(None).is_ok());
+        assert!(RegistrationMode::Closed
+            .check_invite(Some("welcome"))
+            .is_err());
+    }
+}
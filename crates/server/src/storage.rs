@@ -0,0 +1,329 @@
+//  This file contains the `MessageStore` trait used by `App` to persist chat history,
+//  along with the record type it stores. Two implementations are provided: an in-memory
+//  store for tests and ephemeral servers, and a SQLite store so history survives restarts.
+use chrono::{DateTime, Utc};
+use protocol::{direct_room_participants, MessageType, Reactions};
+use std::collections::HashMap;
+use std::fmt;
+
+mod memory;
+mod sqlite;
+
+pub use memory::InMemoryStore;
+pub use sqlite::SqliteStore;
+
+pub const DEFAULT_HISTORY_DB: &str = "history.db";
+
+pub use protocol::MessageId;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredMessage {
+    pub id: MessageId,
+    pub timestamp: DateTime<Utc>,
+    pub room: String,
+    pub sender: String,
+    pub content: String,
+    pub reply_to: Option<MessageId>, // the message that started this reply's thread
+    pub edited_at: Option<DateTime<Utc>>,
+    pub deleted: bool,
+    pub reactions: Reactions,
+}
+
+impl StoredMessage {
+    // The `ChatMessage` sent to clients for this record, flagging the users it mentions
+    pub fn into_chat_message(self, mentions: Vec<String>) -> MessageType {
+        // Direct messages are addressed to whichever participant did not send them
+        let recipient = direct_room_participants(&self.room).map(|(first, second)| {
+            if self.sender == first {
+                second.to_string()
+            } else {
+                first.to_string()
+            }
+        });
+        MessageType::ChatMessage {
+            id: self.id,
+            timestamp: self.timestamp,
+            sender: self.sender,
+            content: self.content,
+            room: self.room,
+            recipient,
+            reply_to: self.reply_to,
+            mentions,
+            edited_at: self.edited_at,
+            deleted: self.deleted,
+            reactions: self.reactions,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Sqlite(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
+pub trait MessageStore: Send + Sync {
+    // Store a new message in `room`, assigning it an ID and the current time. Replies name
+    // the first message of their thread in `reply_to`.
+    fn append(
+        &mut self,
+        room: &str,
+        sender: &str,
+        content: &str,
+        reply_to: Option<MessageId>,
+    ) -> Result<StoredMessage, StorageError>;
+
+    // Up to `limit` messages in `room` older than `before_id` (or the newest ones when
+    // `None`), returned oldest first
+    fn page(
+        &self,
+        room: &str,
+        before_id: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, StorageError>;
+
+    // Up to `limit` messages in `room` newer than `after_id`, returned oldest first
+    fn since(
+        &self,
+        room: &str,
+        after_id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, StorageError>;
+
+    // Every room that has stored messages
+    fn rooms(&self) -> Result<Vec<String>, StorageError>;
+
+    fn get(&self, id: MessageId) -> Result<Option<StoredMessage>, StorageError>;
+
+    // Replace a message's content and record when it was edited, returning the updated
+    // record or `None` if there is no such message
+    fn edit(&mut self, id: MessageId, content: &str)
+        -> Result<Option<StoredMessage>, StorageError>;
+
+    // Turn a message into a tombstone: it keeps its place in history but loses its content
+    // and reactions
+    fn delete(&mut self, id: MessageId) -> Result<Option<StoredMessage>, StorageError>;
+
+    // Add (`present`) or remove a user's reaction, returning whether anything changed
+    fn set_reaction(
+        &mut self,
+        id: MessageId,
+        emoji: &str,
+        username: &str,
+        present: bool,
+    ) -> Result<bool, StorageError>;
+
+    // Record when `username` was last connected
+    fn set_last_seen(&mut self, username: &str, at: DateTime<Utc>) -> Result<(), StorageError>;
+
+    // Username -> when they were last connected, for every user seen so far
+    fn last_seen(&self) -> Result<HashMap<String, DateTime<Utc>>, StorageError>;
+
+    // Record that `username` has read `room` up to message `id`. Positions only move
+    // forward, so a report from an older view is ignored.
+    fn set_last_read(
+        &mut self,
+        username: &str,
+        room: &str,
+        id: MessageId,
+    ) -> Result<(), StorageError>;
+
+    // Room -> ID of the newest message `username` has read there
+    fn last_read(&self, username: &str) -> Result<HashMap<String, MessageId>, StorageError>;
+}
+
+// Pick a store based on `HISTORY_DB`: a SQLite file path (default `history.db`),
+// or `:memory:` to keep history in memory only.
+pub fn open_from_env() -> Result<Box<dyn MessageStore>, StorageError> {
+    let path = std::env::var("HISTORY_DB").unwrap_or_else(|_| DEFAULT_HISTORY_DB.to_string());
+    if path == ":memory:" {
+        println!("Message history is kept in memory only");
+        Ok(Box::new(InMemoryStore::new(
+            InMemoryStore::DEFAULT_CAPACITY,
+        )))
+    } else {
+        println!("Message history is stored in {}", path);
+        Ok(Box::new(SqliteStore::open(&path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::DEFAULT_ROOM;
+
+    // Behaviour every store must share
+    fn exercise_store(store: &mut dyn MessageStore) {
+        assert!(store.page(DEFAULT_ROOM, None, 10).unwrap().is_empty());
+
+        let first = store.append(DEFAULT_ROOM, "user1", "hello", None).unwrap();
+        let second = store
+            .append(DEFAULT_ROOM, "user2", "hi there", None)
+            .unwrap();
+        let third = store
+            .append(DEFAULT_ROOM, "user1", "again", Some(first.id))
+            .unwrap();
+        assert!(second.id > first.id);
+        assert!(second.timestamp >= first.timestamp);
+        assert_eq!(third.reply_to, Some(first.id));
+
+        let recent = store.page(DEFAULT_ROOM, None, 10).unwrap();
+        assert_eq!(recent, vec![first.clone(), second.clone(), third.clone()]);
+        assert_eq!(
+            store.page(DEFAULT_ROOM, None, 1).unwrap(),
+            vec![third.clone()]
+        );
+
+        // Paging backwards from a cursor
+        assert_eq!(
+            store.page(DEFAULT_ROOM, Some(third.id), 1).unwrap(),
+            vec![second.clone()]
+        );
+        assert_eq!(
+            store.page(DEFAULT_ROOM, Some(second.id), 10).unwrap(),
+            vec![first.clone()]
+        );
+        assert!(store
+            .page(DEFAULT_ROOM, Some(first.id), 10)
+            .unwrap()
+            .is_empty());
+
+        // Catching up from a known message
+        assert_eq!(
+            store.since(DEFAULT_ROOM, first.id, 10).unwrap(),
+            vec![second.clone(), third.clone()]
+        );
+        assert_eq!(
+            store.since(DEFAULT_ROOM, first.id, 1).unwrap(),
+            vec![second.clone()]
+        );
+        assert!(store.since(DEFAULT_ROOM, third.id, 10).unwrap().is_empty());
+
+        // Rooms keep separate histories
+        let other = store.append("#ops", "user2", "elsewhere", None).unwrap();
+        assert_eq!(store.page("#ops", None, 10).unwrap(), vec![other.clone()]);
+        assert_eq!(
+            store.page(DEFAULT_ROOM, None, 1).unwrap(),
+            vec![third.clone()]
+        );
+
+        let mut rooms = store.rooms().unwrap();
+        rooms.sort();
+        assert_eq!(rooms, vec![DEFAULT_ROOM.to_string(), "#ops".to_string()]);
+
+        // Reactions are aggregated per emoji and only change once per user
+        assert!(store.set_reaction(first.id, "👍", "user2", true).unwrap());
+        assert!(!store.set_reaction(first.id, "👍", "user2", true).unwrap());
+        assert!(store.set_reaction(first.id, "👍", "user1", true).unwrap());
+        assert!(store.set_reaction(second.id, "🎉", "user1", true).unwrap());
+        let reacted = store.page(DEFAULT_ROOM, Some(third.id), 10).unwrap();
+        assert_eq!(
+            reacted[0].reactions.get("👍"),
+            Some(&vec!["user2".to_string(), "user1".to_string()])
+        );
+        assert_eq!(reacted[1].reactions.len(), 1);
+        assert!(store.set_reaction(second.id, "🎉", "user1", false).unwrap());
+        assert!(!store.set_reaction(second.id, "🎉", "user1", false).unwrap());
+        assert!(store.get(second.id).unwrap().unwrap().reactions.is_empty());
+
+        // Edits and deletions are visible to later reads
+        let edited = store.edit(second.id, "hi there!").unwrap().unwrap();
+        assert_eq!(edited.content, "hi there!");
+        assert!(edited.edited_at.is_some());
+        assert_eq!(store.get(second.id).unwrap(), Some(edited));
+
+        let deleted = store.delete(first.id).unwrap().unwrap();
+        assert!(deleted.deleted);
+        assert!(deleted.content.is_empty());
+        assert!(deleted.reactions.is_empty());
+        assert_eq!(
+            store.page(DEFAULT_ROOM, Some(second.id), 10).unwrap(),
+            vec![deleted]
+        );
+        assert_eq!(store.get(9999).unwrap(), None);
+        assert_eq!(store.edit(9999, "nothing").unwrap(), None);
+
+        // Last-seen times keep the latest value per user
+        let earlier = DateTime::from_timestamp_millis(1_700_000_000_000).unwrap();
+        let later = DateTime::from_timestamp_millis(1_700_000_360_000).unwrap();
+        store.set_last_seen("user1", earlier).unwrap();
+        store.set_last_seen("user2", earlier).unwrap();
+        store.set_last_seen("user1", later).unwrap();
+        let last_seen = store.last_seen().unwrap();
+        assert_eq!(last_seen.len(), 2);
+        assert_eq!(last_seen["user1"], later);
+        assert_eq!(last_seen["user2"], earlier);
+
+        // Read positions are kept per user and room and never move backwards
+        assert!(store.last_read("user1").unwrap().is_empty());
+        store
+            .set_last_read("user1", DEFAULT_ROOM, second.id)
+            .unwrap();
+        store
+            .set_last_read("user1", DEFAULT_ROOM, first.id)
+            .unwrap();
+        store.set_last_read("user1", "#ops", other.id).unwrap();
+        store
+            .set_last_read("user2", DEFAULT_ROOM, third.id)
+            .unwrap();
+        let last_read = store.last_read("user1").unwrap();
+        assert_eq!(last_read.len(), 2);
+        assert_eq!(last_read[DEFAULT_ROOM], second.id);
+        assert_eq!(last_read["#ops"], other.id);
+    }
+
+    #[test]
+    fn in_memory_store() {
+        exercise_store(&mut InMemoryStore::new(100));
+    }
+
+    #[test]
+    fn in_memory_store_drops_oldest_past_capacity() {
+        let mut store = InMemoryStore::new(2);
+        store.append(DEFAULT_ROOM, "user1", "one", None).unwrap();
+        store.append(DEFAULT_ROOM, "user1", "two", None).unwrap();
+        store.append(DEFAULT_ROOM, "user1", "three", None).unwrap();
+        let contents: Vec<String> = store
+            .page(DEFAULT_ROOM, None, 10)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.content)
+            .collect();
+        assert_eq!(contents, vec!["two", "three"]);
+    }
+
+    #[test]
+    fn sqlite_store() {
+        exercise_store(&mut SqliteStore::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn sqlite_store_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("history-{}.db", uuid::Uuid::new_v4()));
+        let stored = {
+            let mut store = SqliteStore::open(&path).unwrap();
+            store
+                .append(DEFAULT_ROOM, "user1", "persisted", None)
+                .unwrap()
+        };
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.page(DEFAULT_ROOM, None, 10).unwrap(), vec![stored]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+//  This file contains `InMemoryStore`, a `MessageStore` that keeps a bounded window of
+//  messages per room in memory. History is lost when the server stops.
+use super::{MessageId, MessageStore, StorageError, StoredMessage};
+use chrono::{DateTime, Utc};
+use protocol::Reactions;
+use std::collections::{HashMap, VecDeque};
+
+pub struct InMemoryStore {
+    rooms: HashMap<String, VecDeque<StoredMessage>>,
+    capacity: usize,
+    next_id: MessageId,
+    last_seen: HashMap<String, DateTime<Utc>>,
+    last_read: HashMap<(String, String), MessageId>, // (username, room) -> message ID
+}
+
+impl InMemoryStore {
+    pub const DEFAULT_CAPACITY: usize = 1000;
+
+    pub fn new(capacity: usize) -> InMemoryStore {
+        InMemoryStore {
+            rooms: HashMap::new(),
+            capacity,
+            next_id: 1,
+            last_seen: HashMap::new(),
+            last_read: HashMap::new(),
+        }
+    }
+}
+
+impl InMemoryStore {
+    fn find_mut(&mut self, id: MessageId) -> Option<&mut StoredMessage> {
+        self.rooms.values_mut().find_map(|messages| {
+            let index = messages.partition_point(|m| m.id < id);
+            messages.get_mut(index).filter(|m| m.id == id)
+        })
+    }
+}
+
+impl MessageStore for InMemoryStore {
+    fn append(
+        &mut self,
+        room: &str,
+        sender: &str,
+        content: &str,
+        reply_to: Option<MessageId>,
+    ) -> Result<StoredMessage, StorageError> {
+        let message = StoredMessage {
+            id: self.next_id,
+            timestamp: Utc::now(),
+            room: room.to_string(),
+            sender: sender.to_string(),
+            content: content.to_string(),
+            reply_to,
+            edited_at: None,
+            deleted: false,
+            reactions: Reactions::new(),
+        };
+        self.next_id += 1;
+
+        let messages = self.rooms.entry(room.to_string()).or_default();
+        if messages.len() == self.capacity {
+            messages.pop_front(); // Remove oldest message if full
+        }
+        messages.push_back(message.clone());
+        Ok(message)
+    }
+
+    fn page(
+        &self,
+        room: &str,
+        before_id: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, StorageError> {
+        let Some(messages) = self.rooms.get(room) else {
+            return Ok(Vec::new());
+        };
+        // IDs are assigned in order, so everything before the cursor is a prefix
+        let end = match before_id {
+            Some(before_id) => messages.partition_point(|m| m.id < before_id),
+            None => messages.len(),
+        };
+        let start = end.saturating_sub(limit);
+        Ok(messages.range(start..end).cloned().collect())
+    }
+
+    fn since(
+        &self,
+        room: &str,
+        after_id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, StorageError> {
+        let Some(messages) = self.rooms.get(room) else {
+            return Ok(Vec::new());
+        };
+        let start = messages.partition_point(|m| m.id <= after_id);
+        Ok(messages.range(start..).take(limit).cloned().collect())
+    }
+
+    fn rooms(&self) -> Result<Vec<String>, StorageError> {
+        Ok(self.rooms.keys().cloned().collect())
+    }
+
+    fn get(&self, id: MessageId) -> Result<Option<StoredMessage>, StorageError> {
+        Ok(self.rooms.values().find_map(|messages| {
+            let index = messages.partition_point(|m| m.id < id);
+            messages.get(index).filter(|m| m.id == id).cloned()
+        }))
+    }
+
+    fn edit(
+        &mut self,
+        id: MessageId,
+        content: &str,
+    ) -> Result<Option<StoredMessage>, StorageError> {
+        Ok(self.find_mut(id).map(|message| {
+            message.content = content.to_string();
+            message.edited_at = Some(Utc::now());
+            message.clone()
+        }))
+    }
+
+    fn delete(&mut self, id: MessageId) -> Result<Option<StoredMessage>, StorageError> {
+        Ok(self.find_mut(id).map(|message| {
+            message.content.clear();
+            message.deleted = true;
+            message.reactions.clear();
+            message.clone()
+        }))
+    }
+
+    fn set_reaction(
+        &mut self,
+        id: MessageId,
+        emoji: &str,
+        username: &str,
+        present: bool,
+    ) -> Result<bool, StorageError> {
+        let Some(message) = self.find_mut(id) else {
+            return Ok(false);
+        };
+        let users = message.reactions.entry(emoji.to_string()).or_default();
+        let index = users.iter().position(|user| user == username);
+        let changed = match (present, index) {
+            (true, None) => {
+                users.push(username.to_string());
+                true
+            }
+            (false, Some(index)) => {
+                users.remove(index);
+                true
+            }
+            _ => false,
+        };
+        if users.is_empty() {
+            message.reactions.remove(emoji);
+        }
+        Ok(changed)
+    }
+
+    fn set_last_seen(&mut self, username: &str, at: DateTime<Utc>) -> Result<(), StorageError> {
+        self.last_seen.insert(username.to_string(), at);
+        Ok(())
+    }
+
+    fn last_seen(&self) -> Result<HashMap<String, DateTime<Utc>>, StorageError> {
+        Ok(self.last_seen.clone())
+    }
+
+    fn set_last_read(
+        &mut self,
+        username: &str,
+        room: &str,
+        id: MessageId,
+    ) -> Result<(), StorageError> {
+        let position = self
+            .last_read
+            .entry((username.to_string(), room.to_string()))
+            .or_default();
+        *position = (*position).max(id);
+        Ok(())
+    }
+
+    fn last_read(&self, username: &str) -> Result<HashMap<String, MessageId>, StorageError> {
+        Ok(self
+            .last_read
+            .iter()
+            .filter(|((user, _), _)| user == username)
+            .map(|((_, room), id)| (room.clone(), *id))
+            .collect())
+    }
+}
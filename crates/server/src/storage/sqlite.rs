@@ -0,0 +1,346 @@
+//  This file contains `SqliteStore`, a `MessageStore` backed by a SQLite database file.
+//  The schema is versioned with `PRAGMA user_version`; new migrations are appended to
+//  `MIGRATIONS` and applied in order when the database is opened.
+use super::{MessageId, MessageStore, StorageError, StoredMessage};
+use chrono::{DateTime, Utc};
+use protocol::Reactions;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+const MIGRATIONS: &[&str] = &[
+    // 1: chat messages
+    "CREATE TABLE messages (
+        id        INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL, -- milliseconds since the Unix epoch, UTC
+        sender    TEXT    NOT NULL,
+        content   TEXT    NOT NULL
+    );",
+    // 2: rooms, existing messages belong to the default room
+    "ALTER TABLE messages ADD COLUMN room TEXT NOT NULL DEFAULT '#general';
+     CREATE INDEX messages_room_id ON messages (room, id);",
+    // 3: edits and deletions
+    "ALTER TABLE messages ADD COLUMN edited_at INTEGER; -- milliseconds, NULL if never edited
+     ALTER TABLE messages ADD COLUMN deleted INTEGER NOT NULL DEFAULT 0;",
+    // 4: reactions, one row per user and emoji
+    "CREATE TABLE reactions (
+        message_id INTEGER NOT NULL REFERENCES messages (id),
+        emoji      TEXT    NOT NULL,
+        username   TEXT    NOT NULL,
+        PRIMARY KEY (message_id, emoji, username)
+    );",
+    // 5: threaded replies
+    "ALTER TABLE messages ADD COLUMN reply_to INTEGER REFERENCES messages (id);",
+    // 6: when each user was last connected
+    "CREATE TABLE last_seen (
+        username  TEXT    PRIMARY KEY,
+        timestamp INTEGER NOT NULL -- milliseconds since the Unix epoch, UTC
+    );",
+    // 7: read receipts
+    "CREATE TABLE read_positions (
+        username   TEXT    NOT NULL,
+        room       TEXT    NOT NULL,
+        message_id INTEGER NOT NULL,
+        PRIMARY KEY (username, room)
+    );",
+];
+
+const MESSAGE_COLUMNS: &str = "id, timestamp, room, sender, content, reply_to, edited_at, deleted";
+
+pub struct SqliteStore {
+    // `Connection` is not `Sync`; the mutex lets the store live inside the shared `App`
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<SqliteStore, StorageError> {
+        let conn = Connection::open(path)?;
+        // Write-ahead logging keeps readers from blocking the writer
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::init(conn)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<SqliteStore, StorageError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<SqliteStore, StorageError> {
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", index + 1)?;
+            tx.commit()?;
+        }
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        // A panic while holding the lock cannot leave SQLite itself inconsistent
+        self.conn
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn message_from_row(row: &Row) -> rusqlite::Result<StoredMessage> {
+    let id: i64 = row.get("id")?;
+    let millis: i64 = row.get("timestamp")?;
+    let edited_millis: Option<i64> = row.get("edited_at")?;
+    let reply_to: Option<i64> = row.get("reply_to")?;
+    Ok(StoredMessage {
+        id: id as MessageId,
+        timestamp: DateTime::from_timestamp_millis(millis).unwrap_or_default(),
+        room: row.get("room")?,
+        sender: row.get("sender")?,
+        content: row.get("content")?,
+        reply_to: reply_to.map(|id| id as MessageId),
+        edited_at: edited_millis.and_then(DateTime::from_timestamp_millis),
+        deleted: row.get("deleted")?,
+        reactions: Reactions::new(),
+    })
+}
+
+// Fill in the reactions of `messages`, which are sorted by ID
+fn load_reactions(conn: &Connection, messages: &mut [StoredMessage]) -> rusqlite::Result<()> {
+    let (Some(first), Some(last)) = (messages.first(), messages.last()) else {
+        return Ok(());
+    };
+    let mut stmt = conn.prepare(
+        "SELECT message_id, emoji, username FROM reactions
+         WHERE message_id BETWEEN ?1 AND ?2 ORDER BY rowid",
+    )?;
+    let mut rows = stmt.query(params![first.id as i64, last.id as i64])?;
+    while let Some(row) = rows.next()? {
+        let id = row.get::<_, i64>(0)? as MessageId;
+        if let Ok(index) = messages.binary_search_by_key(&id, |m| m.id) {
+            messages[index]
+                .reactions
+                .entry(row.get(1)?)
+                .or_default()
+                .push(row.get(2)?);
+        }
+    }
+    Ok(())
+}
+
+// Current time rounded to the millisecond precision the database stores
+fn now_millis() -> DateTime<Utc> {
+    let now = Utc::now();
+    DateTime::from_timestamp_millis(now.timestamp_millis()).unwrap_or(now)
+}
+
+impl MessageStore for SqliteStore {
+    fn append(
+        &mut self,
+        room: &str,
+        sender: &str,
+        content: &str,
+        reply_to: Option<MessageId>,
+    ) -> Result<StoredMessage, StorageError> {
+        let conn = self.conn();
+        // Round to the stored precision so the returned record matches later reads
+        let timestamp = now_millis();
+        conn.execute(
+            "INSERT INTO messages (timestamp, room, sender, content, reply_to)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                timestamp.timestamp_millis(),
+                room,
+                sender,
+                content,
+                reply_to.map(|id| id as i64)
+            ],
+        )?;
+        Ok(StoredMessage {
+            id: conn.last_insert_rowid() as MessageId,
+            timestamp,
+            room: room.to_string(),
+            sender: sender.to_string(),
+            content: content.to_string(),
+            reply_to,
+            edited_at: None,
+            deleted: false,
+            reactions: Reactions::new(),
+        })
+    }
+
+    fn page(
+        &self,
+        room: &str,
+        before_id: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, StorageError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM messages
+             WHERE room = ?1 AND (?2 IS NULL OR id < ?2)
+             ORDER BY id DESC LIMIT ?3",
+            MESSAGE_COLUMNS
+        ))?;
+        let mut messages = stmt
+            .query_map(
+                params![room, before_id.map(|id| id as i64), limit as i64],
+                message_from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<StoredMessage>>>()?;
+        messages.reverse();
+        load_reactions(&conn, &mut messages)?;
+        Ok(messages)
+    }
+
+    fn since(
+        &self,
+        room: &str,
+        after_id: MessageId,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, StorageError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM messages
+             WHERE room = ?1 AND id > ?2
+             ORDER BY id ASC LIMIT ?3",
+            MESSAGE_COLUMNS
+        ))?;
+        let mut messages = stmt
+            .query_map(
+                params![room, after_id as i64, limit as i64],
+                message_from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<StoredMessage>>>()?;
+        load_reactions(&conn, &mut messages)?;
+        Ok(messages)
+    }
+
+    fn rooms(&self) -> Result<Vec<String>, StorageError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT DISTINCT room FROM messages")?;
+        let rooms = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(rooms)
+    }
+
+    fn get(&self, id: MessageId) -> Result<Option<StoredMessage>, StorageError> {
+        let conn = self.conn();
+        let message = conn
+            .query_row(
+                &format!("SELECT {} FROM messages WHERE id = ?1", MESSAGE_COLUMNS),
+                params![id as i64],
+                message_from_row,
+            )
+            .optional()?;
+        let mut messages: Vec<StoredMessage> = message.into_iter().collect();
+        load_reactions(&conn, &mut messages)?;
+        Ok(messages.pop())
+    }
+
+    fn edit(
+        &mut self,
+        id: MessageId,
+        content: &str,
+    ) -> Result<Option<StoredMessage>, StorageError> {
+        let updated = self.conn().execute(
+            "UPDATE messages SET content = ?2, edited_at = ?3 WHERE id = ?1",
+            params![id as i64, content, now_millis().timestamp_millis()],
+        )?;
+        if updated == 0 {
+            return Ok(None);
+        }
+        self.get(id)
+    }
+
+    fn delete(&mut self, id: MessageId) -> Result<Option<StoredMessage>, StorageError> {
+        {
+            let mut conn = self.conn();
+            let tx = conn.transaction()?;
+            let updated = tx.execute(
+                "UPDATE messages SET content = '', deleted = 1 WHERE id = ?1",
+                params![id as i64],
+            )?;
+            if updated == 0 {
+                return Ok(None);
+            }
+            tx.execute(
+                "DELETE FROM reactions WHERE message_id = ?1",
+                params![id as i64],
+            )?;
+            tx.commit()?;
+        }
+        self.get(id)
+    }
+
+    fn set_reaction(
+        &mut self,
+        id: MessageId,
+        emoji: &str,
+        username: &str,
+        present: bool,
+    ) -> Result<bool, StorageError> {
+        let conn = self.conn();
+        let changed = if present {
+            conn.execute(
+                "INSERT OR IGNORE INTO reactions (message_id, emoji, username) VALUES (?1, ?2, ?3)",
+                params![id as i64, emoji, username],
+            )?
+        } else {
+            conn.execute(
+                "DELETE FROM reactions WHERE message_id = ?1 AND emoji = ?2 AND username = ?3",
+                params![id as i64, emoji, username],
+            )?
+        };
+        Ok(changed > 0)
+    }
+
+    fn set_last_seen(&mut self, username: &str, at: DateTime<Utc>) -> Result<(), StorageError> {
+        self.conn().execute(
+            "INSERT OR REPLACE INTO last_seen (username, timestamp) VALUES (?1, ?2)",
+            params![username, at.timestamp_millis()],
+        )?;
+        Ok(())
+    }
+
+    fn last_seen(&self) -> Result<HashMap<String, DateTime<Utc>>, StorageError> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT username, timestamp FROM last_seen")?;
+        let last_seen = stmt
+            .query_map([], |row| {
+                let millis: i64 = row.get(1)?;
+                Ok((
+                    row.get(0)?,
+                    DateTime::from_timestamp_millis(millis).unwrap_or_default(),
+                ))
+            })?
+            .collect::<rusqlite::Result<HashMap<String, DateTime<Utc>>>>()?;
+        Ok(last_seen)
+    }
+
+    fn set_last_read(
+        &mut self,
+        username: &str,
+        room: &str,
+        id: MessageId,
+    ) -> Result<(), StorageError> {
+        self.conn().execute(
+            "INSERT INTO read_positions (username, room, message_id) VALUES (?1, ?2, ?3)
+             ON CONFLICT (username, room)
+             DO UPDATE SET message_id = MAX(message_id, excluded.message_id)",
+            params![username, room, id],
+        )?;
+        Ok(())
+    }
+
+    fn last_read(&self, username: &str) -> Result<HashMap<String, MessageId>, StorageError> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT room, message_id FROM read_positions WHERE username = ?1")?;
+        let last_read = stmt
+            .query_map(params![username], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<HashMap<String, MessageId>>>()?;
+        Ok(last_read)
+    }
+}
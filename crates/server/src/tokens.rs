@@ -0,0 +1,83 @@
+//  This file contains the session tokens handed out on login. A client that loses its
+//  connection presents its token to resume the session without sending the password again.
+//  Tokens live in memory, so they do not survive a server restart, and expire once unused
+//  for `SESSION_TOKEN_TTL`.
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// How long a token stays valid after it was issued or last used
+pub const SESSION_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+struct SessionToken {
+    username: String,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+pub struct SessionTokens {
+    tokens: HashMap<String, SessionToken>,
+}
+
+impl SessionTokens {
+    // Create a token for a freshly authenticated user, dropping any that have expired
+    pub fn issue(&mut self, username: &str, now: Instant) -> String {
+        self.tokens
+            .retain(|_, token| now.duration_since(token.last_used) < SESSION_TOKEN_TTL);
+        let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        self.tokens.insert(
+            token.clone(),
+            SessionToken {
+                username: username.to_string(),
+                last_used: now,
+            },
+        );
+        token
+    }
+
+    // The user a token belongs to, if it is known and has not expired. Using a token keeps
+    // it alive for another `SESSION_TOKEN_TTL`.
+    pub fn resume(&mut self, token: &str, now: Instant) -> Option<String> {
+        let session = self.tokens.get_mut(token)?;
+        if now.duration_since(session.last_used) >= SESSION_TOKEN_TTL {
+            self.tokens.remove(token);
+            return None;
+        }
+        session.last_used = now;
+        Some(session.username.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_known_tokens() {
+        let mut tokens = SessionTokens::default();
+        let now = Instant::now();
+        let token = tokens.issue("user1", now);
+        let other = tokens.issue("user1", now);
+        assert_ne!(token, other);
+
+        assert_eq!(tokens.resume(&token, now), Some("user1".to_string()));
+        assert_eq!(tokens.resume("not-a-token", now), None);
+        assert_eq!(tokens.resume(&other, now), Some("user1".to_string()));
+    }
+
+    #[test]
+    fn tokens_expire_unless_used() {
+        let mut tokens = SessionTokens::default();
+        let start = Instant::now();
+        let token = tokens.issue("user1", start);
+        let unused = tokens.issue("user1", start);
+
+        // Using a token renews it
+        let halfway = start + SESSION_TOKEN_TTL / 2;
+        assert!(tokens.resume(&token, halfway).is_some());
+
+        let later = start + SESSION_TOKEN_TTL;
+        assert_eq!(tokens.resume(&unused, later), None);
+        assert_eq!(tokens.resume(&token, later), Some("user1".to_string()));
+    }
+}
@@ -1,18 +1,22 @@
 //  This file contains functions related to handling WebSocket connections.
 //  It includes a function for starting the WebSocket task,
 //  handling individual connections, and processing incoming and outgoing messages.
+use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
+use protocol::{MessageId, MessageType};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{broadcast, mpsc, Mutex};
 use tokio::time::{timeout, Duration};
-use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
+use tokio_tungstenite::{accept_async, tungstenite::protocol::Message, WebSocketStream};
 use uuid::Uuid; //  unique IDs for users
 
-use crate::app::{App, MessageType};
+use crate::app::{App, TYPING_EXPIRY};
 use crate::commander::command_handler::handle_command;
+use crate::credentials::verify_password;
+use crate::registration::register_account;
 
 pub async fn websocket_task(
     addr: SocketAddr,
@@ -20,7 +24,7 @@ pub async fn websocket_task(
     shutdown: broadcast::Sender<()>,
 ) {
     let listener = TcpListener::bind(addr).await.expect("Failed to bind");
-    println!("Server listening on {}", addr.to_string());
+    println!("Server listening on {}", addr);
 
     let clients = Arc::new(Mutex::new(HashMap::<
         String,
@@ -53,11 +57,11 @@ pub async fn websocket_task(
 }
 
 async fn handle_connection(
-    stream: tokio::net::TcpStream,
+    stream: TcpStream,
     clients: Arc<Mutex<HashMap<String, mpsc::UnboundedSender<MessageType>>>>,
     app: Arc<Mutex<App>>,
     mut shutdown: broadcast::Receiver<()>,
-    batch_tx: mpsc::Sender<MessageType>,
+    _batch_tx: mpsc::Sender<MessageType>,
 ) {
     let ws_stream = accept_async(stream).await.expect("Error during handshake");
 
@@ -69,77 +73,191 @@ async fn handle_connection(
 
     // Step 1: Authenticate the user before proceeding
     let mut authenticated = false;
-    let mut login_attempts = 0; // Add counter for failed login attempts
-    let max_attempts = 5;
+    // Set when a session is resumed with a token: the newest message the client already has
+    let mut resumed_after: Option<MessageId> = None;
+    let mut login_attempts: u8 = 0; // Add counter for failed login attempts
+    let max_attempts: u8 = 5;
 
     while let Some(result) = incoming.next().await {
-        if let Ok(Message::Text(text)) = result {
-            if let Ok(MessageType::SystemMessage(auth_msg)) =
-                serde_json::from_str::<MessageType>(&text)
-            {
-                // Expecting a username and password in the form "username:password"
-                let creds: Vec<&str> = auth_msg.split(':').collect();
-                if creds.len() == 2 {
-                    let username = creds[0];
-                    let password = creds[1];
-
-                    // Authenticate user
-                    let is_authenticated = app.lock().await.authenticate_user(username, password);
-                    if is_authenticated {
-                        authenticated = true;
-
-                        // Add the user to the App with authenticated username
-                        app.lock()
-                            .await
-                            .add_connected_user(client_id.clone(), username.to_string())
-                            .await;
+        let text = match result {
+            Ok(Message::Text(text)) => text,
+            _ => continue,
+        };
+
+        match MessageType::from_json(&text) {
+            Ok(MessageType::Login { username, password }) => {
+                // Authenticate user. Hashing is CPU bound, so verify on the blocking pool.
+                let stored_hash = app.lock().await.password_hash(&username);
+                let is_authenticated = tokio::task::spawn_blocking(move || {
+                    verify_password(stored_hash.as_deref(), &password)
+                })
+                .await
+                .unwrap_or(false);
 
-                        let success_message =
-                            MessageType::SystemMessage("Authentication successful".to_string());
-                        tx_original.send(success_message).unwrap();
-                        clients
-                            .lock()
-                            .await
-                            .insert(client_id.clone(), tx_original.clone());
-
-                        break; // User is authenticated, proceed
-                    } else {
-                        login_attempts += 1; // Increment failed attempts
-                        let remaining_attempts = max_attempts - login_attempts;
-                        let fail_message = MessageType::SystemMessage(format!(
-                            "Authentication failed. {} attempts remaining.",
-                            remaining_attempts
-                        ));
-                        tx_original.send(fail_message).unwrap();
-                        println!("Authentication Failed.");
-
-                        // If the user exceeds max attempts, close the connection
-                        if login_attempts >= max_attempts {
-                            let max_attempt_message = MessageType::SystemMessage(
-                                "Max login attempts reached. Closing connection.".to_string(),
-                            );
-                            tx_original.send(max_attempt_message).unwrap();
-                            println!(
-                                "Max login attempts reached, closing connection for {}",
-                                client_id
-                            );
-                            return; // Exit function, closing the connection
-                        }
+                if is_authenticated {
+                    authenticated = true;
+
+                    // Add the user to the App with authenticated username
+                    let mut app_lock = app.lock().await;
+                    app_lock
+                        .add_connected_user(client_id.clone(), username.clone())
+                        .await;
+                    let token = app_lock.issue_token(&username);
+                    drop(app_lock);
+
+                    tx_original
+                        .send(MessageType::LoginOk { username, token })
+                        .unwrap();
+                    clients
+                        .lock()
+                        .await
+                        .insert(client_id.clone(), tx_original.clone());
+
+                    break; // User is authenticated, proceed
+                } else {
+                    login_attempts += 1; // Increment failed attempts
+                    let remaining_attempts = max_attempts - login_attempts;
+
+                    // The send task is not running yet, so reply on the socket directly
+                    let fail_message = MessageType::LoginFailed { remaining_attempts };
+                    if send_direct(&outgoing, &fail_message).await.is_err() {
+                        return;
+                    }
+                    println!("Authentication Failed.");
+
+                    // If the user exceeds max attempts, close the connection
+                    if remaining_attempts == 0 {
+                        println!(
+                            "Max login attempts reached, closing connection for {}",
+                            client_id
+                        );
+                        let _ = outgoing.lock().await.close().await;
+                        return; // Exit function, closing the connection
+                    }
+                }
+            }
+            Ok(MessageType::Register {
+                username,
+                password,
+                invite_code,
+            }) => match register_account(&app, &username, password, invite_code.as_deref()).await {
+                Ok(()) => {
+                    println!("Registered new account '{}'", username);
+                    authenticated = true;
+
+                    // A new account is logged in straight away
+                    let mut app_lock = app.lock().await;
+                    app_lock
+                        .add_connected_user(client_id.clone(), username.clone())
+                        .await;
+                    let token = app_lock.issue_token(&username);
+                    drop(app_lock);
+
+                    tx_original.send(MessageType::RegisterOk).unwrap();
+                    tx_original
+                        .send(MessageType::LoginOk { username, token })
+                        .unwrap();
+                    clients
+                        .lock()
+                        .await
+                        .insert(client_id.clone(), tx_original.clone());
+
+                    break;
+                }
+                Err(reason) => {
+                    let fail_message = MessageType::RegisterFailed { reason };
+                    if send_direct(&outgoing, &fail_message).await.is_err() {
+                        return;
                     }
                 }
+            },
+            Ok(MessageType::Resume {
+                token,
+                last_seen_id,
+            }) => {
+                let mut app_lock = app.lock().await;
+                let Some(username) = app_lock.resume_session(&token) else {
+                    drop(app_lock);
+                    // A bad token counts as a failed login, so tokens cannot be guessed
+                    // without limit; the client falls back to logging in with a password
+                    login_attempts += 1;
+                    if send_direct(&outgoing, &MessageType::ResumeFailed)
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                    println!("Session resume failed.");
+
+                    if login_attempts >= max_attempts {
+                        println!(
+                            "Max login attempts reached, closing connection for {}",
+                            client_id
+                        );
+                        let _ = outgoing.lock().await.close().await;
+                        return;
+                    }
+                    continue;
+                };
+                println!("{} resumed their session", username);
+                authenticated = true;
+                resumed_after = last_seen_id;
+
+                app_lock
+                    .add_connected_user(client_id.clone(), username.clone())
+                    .await;
+                drop(app_lock);
+
+                tx_original
+                    .send(MessageType::LoginOk { username, token })
+                    .unwrap();
+                clients
+                    .lock()
+                    .await
+                    .insert(client_id.clone(), tx_original.clone());
+
+                break;
             }
+            _ => {}
         }
     }
 
     if !authenticated {
-        println!("Authentication failed, closing connection");
+        // Clients probing whether the server is up connect and leave without logging in
+        if login_attempts == 0 {
+            println!("Connection closed before logging in");
+        } else {
+            println!("Authentication failed, closing connection");
+        }
         return;
     }
 
-    // Send message history to the new client from the App
-    let history = app.lock().await.get_message_history().await;
-    for message in history {
-        tx_original.send(message.clone()).unwrap();
+    // Put the user back in their rooms and bring each room up to date with how far they had
+    // read it and the most recent page of history (older pages are fetched on demand). The
+    // page for a resumed session also covers the messages it missed.
+    let username = app
+        .lock()
+        .await
+        .get_connected_user(&client_id)
+        .await
+        .unwrap()
+        .lock()
+        .await
+        .username
+        .clone();
+    let rooms = app.lock().await.rooms_for_user(&username).await;
+    let read_positions = app.lock().await.read_positions(&username).await;
+    for room in rooms {
+        tx_original
+            .send(MessageType::RoomJoined { room: room.clone() })
+            .unwrap();
+        replay_room(&app, &tx_original, &room, resumed_after, &read_positions).await;
+    }
+
+    // Followed by the user's direct message conversations
+    let direct_rooms = app.lock().await.direct_rooms_for_user(&username).await;
+    for room in direct_rooms {
+        replay_room(&app, &tx_original, &room, resumed_after, &read_positions).await;
     }
 
     let disconnect_handled = Arc::new(Mutex::new(false));
@@ -205,7 +323,7 @@ async fn handle_connection(
 
         tokio::spawn(async move {
             while let Some(message) = rx.recv().await {
-                let serialized_message = serde_json::to_string(&message).unwrap();
+                let serialized_message = message.to_json();
                 let mut outgoing_lock = outgoing_clone.lock().await;
                 if outgoing_lock
                     .send(Message::Text(serialized_message))
@@ -236,7 +354,7 @@ async fn handle_connection(
         tokio::spawn(async move {
             while let Some(result) = incoming.next().await {
                 match result {
-                    Ok(Message::Text(text)) => match serde_json::from_str::<MessageType>(&text) {
+                    Ok(Message::Text(text)) => match MessageType::from_json(&text) {
                         Ok(message) => {
                             handle_incoming_message(
                                 message,
@@ -294,6 +412,37 @@ async fn handle_connection(
     handle_disconnection(disconnect_handled, &client_id, &clients, app).await;
 }
 
+// Send how far the user has read a room, followed by its newest page of history. A resumed
+// session gets a page reaching back to `resumed_after`, the newest message it already has.
+async fn replay_room(
+    app: &Arc<Mutex<App>>,
+    tx: &mpsc::UnboundedSender<MessageType>,
+    room: &str,
+    resumed_after: Option<MessageId>,
+    read_positions: &HashMap<String, MessageId>,
+) {
+    if let Some(&last_read) = read_positions.get(room) {
+        let _ = tx.send(MessageType::ReadPosition {
+            room: room.to_string(),
+            last_read,
+        });
+    }
+    let history = app.lock().await.catch_up_history(room, resumed_after).await;
+    let _ = tx.send(history);
+}
+
+// Write a message straight to the socket, bypassing the per-client send task
+async fn send_direct(
+    outgoing: &Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>,
+    message: &MessageType,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    outgoing
+        .lock()
+        .await
+        .send(Message::Text(message.to_json()))
+        .await
+}
+
 async fn handle_incoming_message(
     message: MessageType,
     client_id: &str,
@@ -301,7 +450,12 @@ async fn handle_incoming_message(
     app: &Arc<Mutex<App>>, // Batch processing sender
 ) {
     match message {
-        MessageType::ChatMessage { sender: _, content } => {
+        MessageType::SendMessage {
+            room,
+            content,
+            nonce,
+            reply_to,
+        } => {
             // Fetch username from App
             let client_name = app
                 .lock()
@@ -314,37 +468,170 @@ async fn handle_incoming_message(
                 .username
                 .clone();
 
-            let broadcast_message = MessageType::ChatMessage {
-                sender: client_name.clone(),
-                content: content.clone(),
+            // A message sent again after a dropped connection was stored the first time; only
+            // its acknowledgement went missing
+            let mut app_lock = app.lock().await;
+            if let Some((id, timestamp)) = app_lock.already_sent(&client_name, &nonce) {
+                drop(app_lock);
+                if let Some(sender) = clients.lock().await.get(client_id) {
+                    let _ = sender.send(MessageType::Ack {
+                        nonce,
+                        id,
+                        timestamp,
+                    });
+                }
+                return;
+            }
+
+            // Only members may post to a room, and replies must belong to a thread in it
+            let reply = if !app_lock.is_room_member(&client_name, &room).await {
+                Err(format!("You are not in {}.", room))
+            } else {
+                match app_lock.thread_parent(&room, reply_to).await {
+                    // Add message to history in App, which assigns its ID and timestamp
+                    Ok(reply_to) => app_lock
+                        .add_message_to_history(&room, &client_name, &content, reply_to)
+                        .await
+                        .map_err(|e| {
+                            println!("Failed to store message from {}: {}", client_name, e);
+                            "Message could not be saved.".to_string()
+                        }),
+                    Err(reason) => Err(reason),
+                }
             };
+            if let Ok(stored) = &reply {
+                app_lock.remember_sent(&client_name, &nonce, stored);
+                app_lock.count_sent_message(client_id).await;
+            }
+            drop(app_lock);
 
-            // Add message to history in App
-            app.lock()
-                .await
-                .add_message_to_history(broadcast_message.clone())
-                .await;
-
-            // Broadcast to all clients
-            let mut clients_lock = clients.lock().await;
-            let disconnected_clients: Vec<String> = clients_lock
-                .iter()
-                .filter_map(|(id, tx)| {
-                    if id == client_id {
-                        None // prevent sending back to yourself
-                    } else if tx.send(broadcast_message.clone()).is_err() {
-                        // If sending fails, mark this client as disconnected
-                        Some(id.clone())
-                    } else {
-                        None
+            let stored = match reply {
+                Ok(stored) => stored,
+                Err(reason) => {
+                    if let Some(sender) = clients.lock().await.get(client_id) {
+                        let _ = sender.send(MessageType::SendFailed { nonce, reason });
                     }
-                })
+                    return;
+                }
+            };
+
+            // Acknowledge before broadcasting so the sender can match its own copy of the
+            // broadcast to the pending message
+            if let Some(sender) = clients.lock().await.get(client_id) {
+                let _ = sender.send(MessageType::Ack {
+                    nonce,
+                    id: stored.id,
+                    timestamp: stored.timestamp,
+                });
+            }
+
+            // Broadcast to every member of the room, the sender included
+            let mut app_lock = app.lock().await;
+            app_lock.stop_typing(&room, &client_name);
+            let recipients = app_lock.room_recipients(&room).await;
+            let message = app_lock.chat_message(stored).await;
+            drop(app_lock);
+            send_to_clients(clients, &recipients, &message).await;
+        }
+
+        MessageType::EditMessage { id, content } => {
+            let mut app_lock = app.lock().await;
+            let username = match app_lock.get_connected_user(client_id).await {
+                Some(user) => user.lock().await.username.clone(),
+                None => return,
+            };
+            let result = app_lock.edit_message(&username, id, &content).await;
+            match result {
+                Ok(stored) => {
+                    let recipients = app_lock.message_recipients(&stored.room).await;
+                    let mentions = app_lock.mentioned_users(&stored.content).await;
+                    drop(app_lock);
+                    let edited = MessageType::MessageEdited {
+                        id,
+                        room: stored.room,
+                        content: stored.content,
+                        mentions,
+                        edited_at: stored.edited_at.unwrap_or(stored.timestamp),
+                    };
+                    send_to_clients(clients, &recipients, &edited).await;
+                }
+                Err(reason) => {
+                    drop(app_lock);
+                    if let Some(sender) = clients.lock().await.get(client_id) {
+                        let _ = sender.send(MessageType::SystemMessage(reason));
+                    }
+                }
+            }
+        }
+
+        MessageType::Typing { room } => {
+            let mut app_lock = app.lock().await;
+            let username = match app_lock.get_connected_user(client_id).await {
+                Some(user) => user.lock().await.username.clone(),
+                None => return,
+            };
+            if !app_lock.is_room_member(&username, &room).await
+                || !app_lock.note_typing(&room, &username)
+            {
+                return;
+            }
+            // Everyone in the room except the typing user's own sessions
+            let own_sessions = app_lock.user_sessions(&username).await;
+            let recipients: Vec<String> = app_lock
+                .room_recipients(&room)
+                .await
+                .into_iter()
+                .filter(|session| !own_sessions.contains(session))
                 .collect();
+            drop(app_lock);
+            let typing = MessageType::UserTyping {
+                room,
+                username,
+                expires_in_ms: TYPING_EXPIRY.as_millis() as u64,
+            };
+            send_to_clients(clients, &recipients, &typing).await;
+        }
 
-            // Remove disconnected clients
-            for id in disconnected_clients {
-                clients_lock.remove(&id);
-                println!("Removed disconnected client: {}", id);
+        MessageType::MarkRead { room, id } => {
+            let mut app_lock = app.lock().await;
+            let username = match app_lock.get_connected_user(client_id).await {
+                Some(user) => user.lock().await.username.clone(),
+                None => return,
+            };
+            app_lock.mark_read(&username, &room, id).await;
+        }
+
+        MessageType::AddReaction { id, emoji } => {
+            handle_reaction(id, &emoji, true, client_id, clients, app).await;
+        }
+
+        MessageType::RemoveReaction { id, emoji } => {
+            handle_reaction(id, &emoji, false, client_id, clients, app).await;
+        }
+
+        MessageType::DeleteMessage { id } => {
+            let mut app_lock = app.lock().await;
+            let username = match app_lock.get_connected_user(client_id).await {
+                Some(user) => user.lock().await.username.clone(),
+                None => return,
+            };
+            let result = app_lock.delete_message(&username, id).await;
+            match result {
+                Ok(stored) => {
+                    let recipients = app_lock.message_recipients(&stored.room).await;
+                    drop(app_lock);
+                    let deleted = MessageType::MessageDeleted {
+                        id,
+                        room: stored.room,
+                    };
+                    send_to_clients(clients, &recipients, &deleted).await;
+                }
+                Err(reason) => {
+                    drop(app_lock);
+                    if let Some(sender) = clients.lock().await.get(client_id) {
+                        let _ = sender.send(MessageType::SystemMessage(reason));
+                    }
+                }
             }
         }
 
@@ -355,6 +642,131 @@ async fn handle_incoming_message(
         MessageType::SystemMessage(system_message) => {
             println!("System message: {}", system_message);
         }
+
+        MessageType::FetchHistory {
+            room,
+            before_id,
+            limit,
+        } => {
+            let app_lock = app.lock().await;
+            let username = match app_lock.get_connected_user(client_id).await {
+                Some(user) => user.lock().await.username.clone(),
+                None => return,
+            };
+            // Room history is only visible to its members. Others get an empty page, so
+            // the client stops waiting for one.
+            let replies = if app_lock.can_read_room(&username, &room).await {
+                vec![
+                    app_lock
+                        .get_message_history(&room, before_id, limit as usize)
+                        .await,
+                ]
+            } else {
+                vec![
+                    MessageType::SystemMessage(format!("You are not in {}.", room)),
+                    MessageType::History {
+                        room,
+                        messages: Vec::new(),
+                        oldest_id: None,
+                        has_more: false,
+                    },
+                ]
+            };
+            drop(app_lock);
+
+            if let Some(sender) = clients.lock().await.get(client_id) {
+                for reply in replies {
+                    let _ = sender.send(reply);
+                }
+            }
+        }
+
+        MessageType::Login { .. } | MessageType::Register { .. } | MessageType::Resume { .. } => {
+            println!(
+                "Ignoring login from already authenticated client {}",
+                client_id
+            );
+        }
+
+        MessageType::ChatMessage { .. }
+        | MessageType::Ack { .. }
+        | MessageType::SendFailed { .. }
+        | MessageType::MessageEdited { .. }
+        | MessageType::MessageDeleted { .. }
+        | MessageType::ReactionChanged { .. }
+        | MessageType::UserTyping { .. }
+        | MessageType::ReadPosition { .. }
+        | MessageType::LoginOk { .. }
+        | MessageType::ResumeFailed
+        | MessageType::LoginFailed { .. }
+        | MessageType::RegisterOk
+        | MessageType::RegisterFailed { .. }
+        | MessageType::History { .. }
+        | MessageType::RoomJoined { .. }
+        | MessageType::RoomLeft { .. }
+        | MessageType::RoomList { .. } => {
+            println!("Unexpected server-only message from client {}", client_id);
+        }
+    }
+}
+
+// Apply a reaction change and broadcast it to everyone who can see the message
+async fn handle_reaction(
+    id: MessageId,
+    emoji: &str,
+    added: bool,
+    client_id: &str,
+    clients: &Arc<Mutex<HashMap<String, mpsc::UnboundedSender<MessageType>>>>,
+    app: &Arc<Mutex<App>>,
+) {
+    let mut app_lock = app.lock().await;
+    let username = match app_lock.get_connected_user(client_id).await {
+        Some(user) => user.lock().await.username.clone(),
+        None => return,
+    };
+    match app_lock.react(&username, id, emoji, added).await {
+        Ok(Some((stored, emoji))) => {
+            let recipients = app_lock.message_recipients(&stored.room).await;
+            drop(app_lock);
+            let changed = MessageType::ReactionChanged {
+                id,
+                room: stored.room,
+                emoji,
+                username,
+                added,
+            };
+            send_to_clients(clients, &recipients, &changed).await;
+        }
+        Ok(None) => {}
+        Err(reason) => {
+            drop(app_lock);
+            if let Some(sender) = clients.lock().await.get(client_id) {
+                let _ = sender.send(MessageType::SystemMessage(reason));
+            }
+        }
+    }
+}
+
+// Send a message to the given clients, dropping any client whose channel has closed
+pub async fn send_to_clients(
+    clients: &Arc<Mutex<HashMap<String, mpsc::UnboundedSender<MessageType>>>>,
+    recipients: &[String],
+    message: &MessageType,
+) {
+    let mut clients_lock = clients.lock().await;
+    let disconnected_clients: Vec<String> = recipients
+        .iter()
+        .filter_map(|id| match clients_lock.get(id) {
+            // If sending fails, mark this client as disconnected
+            Some(tx) if tx.send(message.clone()).is_err() => Some(id.clone()),
+            _ => None,
+        })
+        .collect();
+
+    // Remove disconnected clients
+    for id in disconnected_clients {
+        clients_lock.remove(&id);
+        println!("Removed disconnected client: {}", id);
     }
 }
 
@@ -413,11 +825,17 @@ async fn handle_disconnection(
         .username
         .clone();
 
-    app.lock().await.remove_connected_user(client_id).await;
+    let went_offline = app.lock().await.remove_connected_user(client_id).await;
 
     // Remove the client from the list of connected clients
     clients.lock().await.remove(client_id);
 
+    // The user is still connected on another session
+    if went_offline.is_none() {
+        println!("{} closed one of their sessions", client_name);
+        return;
+    }
+
     // Broadcast that the user has disconnected
     let disconnect_message =
         MessageType::SystemMessage(format!("{} has disconnected.", client_name));